@@ -3,7 +3,7 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use lending_protocol::msg::{ExecuteMsg, QueryMsg};
+use lending_protocol::msg::{ExecuteMsg, FlashLoanReceiverMsg, MigrateMsg, QueryMsg};
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -13,4 +13,6 @@ fn main() {
 
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
+    export_schema(&schema_for!(FlashLoanReceiverMsg), &out_dir);
 }
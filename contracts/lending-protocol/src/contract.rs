@@ -1,37 +1,99 @@
 #[cfg(not(feature = "library"))]
-use cosmwasm_std::{entry_point, Order};
-use cosmwasm_std::{to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult, StdError, Uint128, from_binary, Addr, attr, Timestamp, Decimal, Storage};
-use cw2::set_contract_version;
-use cw20::{Cw20ReceiveMsg, Cw20Contract, Cw20ExecuteMsg,};
+use cosmwasm_std::entry_point;
+use cosmwasm_std::{to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult, StdError, Uint128, from_binary, Addr, attr, Decimal, Storage, QuerierWrapper, CosmosMsg, Order, BankMsg, coins, Reply, SubMsg, WasmMsg, Timestamp, Attribute, to_vec, Empty};
+use cw2::{get_contract_version, set_contract_version};
+use cw20::{Cw20ReceiveMsg, Cw20Contract, Cw20ExecuteMsg, Cw20QueryMsg, BalanceResponse, Expiration};
 
+use crate::enumerable::{query_all_users, query_borrow_delegations, query_loans};
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, QueryMsg, UserInfoResponse, InstantiateMsg, Cw20HookMsg};
-use crate::state::{UserData, USER_INFO, Config, CONFIG, LoanInfo, LOANS};
+use crate::events;
+use crate::interest_rate::{InterestRateModel, InterestRateModelConfig};
+use crate::migrations::{parse_version, MIGRATIONS};
+use crate::msg::{ExecuteMsg, QueryMsg, UserInfoResponse, InstantiateMsg, MigrateMsg, Cw20HookMsg, InterestRateResponse, SupplyInfoResponse, MarketInfo, MarketsResponse, ReservesResponse, RepaymentPolicy, AdminResponse, RoleInfo, RolesResponse, CapacityResponse, MarketCapacity, AccountLiquidityResponse, ConfigResponse, MarketStateResponse, ProjectedDebtResponse, ProjectedLoan, FlashLoanReceiverMsg, DepositPrice};
+use crate::oracle::{query_price, to_value, from_value, PriceResponse};
+use crate::simulation::{simulate_borrow, simulate_payoff, simulate_withdraw};
+use crate::state::{UserData, USER_INFO, Config, CONFIG, LoanInfo, LoanTerm, LOANS, MarketState, MARKET_STATE, RATE_MODEL, MarketConfig, MARKETS, CollateralDeposit, CollateralState, COLLATERAL_STATE, FlashLoanState, FLASH_LOAN, Role, ROLES, PauseState, PAUSE_STATE, BorrowDelegation, BORROW_DELEGATIONS, TERM_LOANS};
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:lending-app";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+const FLASH_LOAN_REPLY_ID: u64 = 1;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     _info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
     let config = Config {
-        admin: deps.api.addr_validate(&msg.admin)?,
-        generic_token: deps.api.addr_validate(&msg.generic_token)?,
+        admin: Some(deps.api.addr_validate(&msg.admin)?),
+        pending_admin: None,
         lending_token: None,
+        receipt_token: None,
+        oracle: deps.api.addr_validate(&msg.oracle)?,
+        max_price_age: msg.max_price_age,
+        close_factor: msg.close_factor,
+        reserve_factor: msg.reserve_factor,
+        treasury: deps.api.addr_validate(&msg.treasury)?,
+        term_loan_rate: msg.term_loan_rate,
+        penalty_rate: msg.penalty_rate,
+        grace_period: msg.grace_period,
+        flash_loan_fee: msg.flash_loan_fee,
+        borrow_cap: None,
     };
+    validate_config(&config)?;
     CONFIG.save(deps.storage, &config)?;
+    let generic_token = deps.api.addr_validate(&msg.generic_token)?;
+    let market = MarketConfig {
+        native: false,
+        collateral_factor: msg.collateral_factor,
+        liquidation_threshold: msg.liquidation_threshold,
+        liquidation_bonus: msg.liquidation_bonus,
+        supply_cap: None,
+    };
+    validate_market(&market)?;
+    MARKETS.save(deps.storage, generic_token.as_str(), &market)?;
+    COLLATERAL_STATE.save(deps.storage, generic_token.as_str(), &CollateralState::default())?;
+    msg.interest_rate_model.validate()?;
+    RATE_MODEL.save(deps.storage, &msg.interest_rate_model)?;
+    MARKET_STATE.save(deps.storage, &MarketState::new(env.block.time))?;
+    PAUSE_STATE.save(deps.storage, &PauseState::default())?;
 
     Ok(Response::new()
         .add_attribute("method", "instantiate")
         .add_attribute("admin", msg.admin)
-        .add_attribute("generic token", config.generic_token))
+        .add_attribute("generic token", generic_token))
+}
+
+/// Upgrades the storage of an older version of this contract, running every
+/// registered migration step from the stored version on
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(mut deps: DepsMut, env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    let stored = get_contract_version(deps.storage)?;
+    if stored.contract != CONTRACT_NAME {
+        return Err(ContractError::InvalidMigration { reason: format!("cannot migrate from {}", stored.contract) });
+    }
+    let stored_version = parse_version(&stored.version)?;
+    if stored_version > parse_version(CONTRACT_VERSION)? {
+        return Err(ContractError::InvalidMigration {
+            reason: format!("cannot downgrade from {} to {}", stored.version, CONTRACT_VERSION),
+        });
+    }
+    for migration in MIGRATIONS {
+        if parse_version(migration.from)? >= stored_version {
+            (migration.run)(deps.branch(), &env, &msg)?;
+        }
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "migrate")
+        .add_attribute("from_version", stored.version)
+        .add_attribute("to_version", CONTRACT_VERSION))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -43,9 +105,76 @@ pub fn execute(
 ) -> Result<Response, ContractError> {
     match msg {
         ExecuteMsg::Receive(_msg) => receive_cw20(deps, info, env, _msg),
-        ExecuteMsg::Withdraw { amount } => try_withdraw(deps, info, env, amount), 
-        ExecuteMsg::Borrow { amount } => try_borrow(deps, info, env, amount),
+        ExecuteMsg::DepositNative {} => try_deposit_native(deps, env, info),
+        ExecuteMsg::Withdraw { token, amount } => try_withdraw(deps, info, env, token, amount),
+        ExecuteMsg::SetCollateral { token, enabled } => try_set_collateral(deps, info, env, token, enabled),
+        ExecuteMsg::Borrow { amount, term, on_behalf_of } => {
+            let borrower = on_behalf_of.map(|owner| deps.api.addr_validate(&owner)).transpose()?;
+            try_borrow(deps, info, env, amount, term, borrower)
+        },
+        ExecuteMsg::ApproveBorrowDelegate { delegate, limit, expires } => {
+            approve_borrow_delegate(deps, env, info, delegate, limit, expires)
+        },
+        ExecuteMsg::Liquidate { borrower, collateral, amount } => {
+            let borrower_addr = deps.api.addr_validate(&borrower)?;
+            try_liquidate(deps, env, info.sender, borrower_addr, collateral, amount, true)
+        },
+        ExecuteMsg::FlashLoan { token, amount, receiver, msg } => try_flash_loan(deps, env, token, amount, receiver, msg),
+        ExecuteMsg::ProposeAdmin { address } => propose_admin(deps, info, address),
+        ExecuteMsg::AcceptAdmin {} => accept_admin(deps, info),
+        ExecuteMsg::RenounceAdmin {} => renounce_admin(deps, info),
+        ExecuteMsg::GrantRole { address, role } => grant_role(deps, info, address, role),
+        ExecuteMsg::RevokeRole { address } => revoke_role(deps, info, address),
+        ExecuteMsg::SetPaused { deposit, borrow, withdraw, payoff, liquidate } => {
+            set_paused(deps, info, deposit, borrow, withdraw, payoff, liquidate)
+        },
         ExecuteMsg::SetLendingTokenAddress { address } => set_lending_token_addr(deps, info, address),
+        ExecuteMsg::SetReceiptTokenAddress { address } => set_receipt_token_addr(deps, info, address),
+        ExecuteMsg::UpdateConfig {
+            oracle,
+            max_price_age,
+            close_factor,
+            reserve_factor,
+            treasury,
+            term_loan_rate,
+            penalty_rate,
+            grace_period,
+            flash_loan_fee,
+        } => update_config(
+            deps,
+            info,
+            oracle,
+            max_price_age,
+            close_factor,
+            reserve_factor,
+            treasury,
+            term_loan_rate,
+            penalty_rate,
+            grace_period,
+            flash_loan_fee,
+        ),
+        ExecuteMsg::ListMarket {
+            token,
+            native,
+            collateral_factor,
+            liquidation_threshold,
+            liquidation_bonus,
+            supply_cap,
+        } => {
+            let market = MarketConfig { native, collateral_factor, liquidation_threshold, liquidation_bonus, supply_cap };
+            list_market(deps, info, token, market)
+        },
+        ExecuteMsg::SetBorrowCap { borrow_cap } => set_borrow_cap(deps, info, borrow_cap),
+        ExecuteMsg::UpdateInterestRateModel { model } => update_interest_rate_model(deps, env, info, model),
+        ExecuteMsg::WithdrawReserves { amount, recipient } => withdraw_reserves(deps, env, info, amount, recipient),
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractError> {
+    match msg.id {
+        FLASH_LOAN_REPLY_ID => settle_flash_loan(deps, env),
+        id => Err(ContractError::CustomError { val: format!("Unknown reply id {}", id) }),
     }
 }
 
@@ -57,204 +186,1629 @@ pub fn receive_cw20(
 ) -> Result<Response, ContractError> {
     match from_binary(&cw20_msg.msg) {
         Ok(Cw20HookMsg::Deposit {}) => {
-            // only asset contract can execute this message
+            // only listed collateral contracts can execute this message
+            let contract_addr = info.sender;
+            match MARKETS.may_load(deps.storage, contract_addr.as_str())? {
+                Some(market) if !market.native => {},
+                _ => return Err(ContractError::Unauthorized {}),
+            }
+
+            let cw20_sender_addr = deps.api.addr_validate(&cw20_msg.sender)?;
+            try_deposit(deps, env, contract_addr.into_string(), cw20_sender_addr, cw20_msg.amount)
+        },
+        Ok(Cw20HookMsg::DepositFor { beneficiary }) => {
+            // only listed collateral contracts can execute this message
+            let contract_addr = info.sender;
+            match MARKETS.may_load(deps.storage, contract_addr.as_str())? {
+                Some(market) if !market.native => {},
+                _ => return Err(ContractError::Unauthorized {}),
+            }
+
+            let beneficiary_addr = deps.api.addr_validate(&beneficiary)?;
+            try_deposit(deps, env, contract_addr.into_string(), beneficiary_addr, cw20_msg.amount)
+        },
+        Ok(Cw20HookMsg::Payoff { loan_id, policy }) => {
+            // only lending token contract can execute this message
             let contract_addr = info.sender;
             let config: Config = CONFIG.load(deps.storage)?;
-            if contract_addr != config.generic_token {
+            if contract_addr != lending_token_addr(&config)? {
                 return Err(ContractError::Unauthorized {});
             }
-
             let cw20_sender_addr = deps.api.addr_validate(&cw20_msg.sender)?;
-            try_deposit(deps, cw20_sender_addr, cw20_msg.amount)
+            let loan_id = loan_id.map(|loan_id| loan_id.u128());
+            let policy = policy.unwrap_or_default();
+            try_payoff(deps, env, cw20_sender_addr.clone(), cw20_sender_addr, cw20_msg.amount, loan_id, policy)
         },
-        Ok(Cw20HookMsg::Payoff { }) => {
+        Ok(Cw20HookMsg::PayoffFor { borrower, loan_id, policy }) => {
             // only lending token contract can execute this message
             let contract_addr = info.sender;
             let config: Config = CONFIG.load(deps.storage)?;
-            if contract_addr != config.lending_token.unwrap() {
+            if contract_addr != lending_token_addr(&config)? {
                 return Err(ContractError::Unauthorized {});
             }
             let cw20_sender_addr = deps.api.addr_validate(&cw20_msg.sender)?;
-            try_payoff(deps, cw20_sender_addr, env, cw20_msg.amount)
-        } 
+            let borrower_addr = deps.api.addr_validate(&borrower)?;
+            let loan_id = loan_id.map(|loan_id| loan_id.u128());
+            try_payoff(deps, env, cw20_sender_addr, borrower_addr, cw20_msg.amount, loan_id, policy.unwrap_or_default())
+        },
+        Ok(Cw20HookMsg::Liquidate { borrower, collateral }) => {
+            // only lending token contract can execute this message
+            let contract_addr = info.sender;
+            let config: Config = CONFIG.load(deps.storage)?;
+            if contract_addr != lending_token_addr(&config)? {
+                return Err(ContractError::Unauthorized {});
+            }
+            let cw20_sender_addr = deps.api.addr_validate(&cw20_msg.sender)?;
+            let borrower_addr = deps.api.addr_validate(&borrower)?;
+            try_liquidate(deps, env, cw20_sender_addr, borrower_addr, collateral, cw20_msg.amount, false)
+        },
+        Ok(Cw20HookMsg::Supply {}) => {
+            // only lending token contract can execute this message
+            let contract_addr = info.sender;
+            let config: Config = CONFIG.load(deps.storage)?;
+            if contract_addr != lending_token_addr(&config)? {
+                return Err(ContractError::Unauthorized {});
+            }
+            let cw20_sender_addr = deps.api.addr_validate(&cw20_msg.sender)?;
+            try_supply(deps, env, cw20_sender_addr, cw20_msg.amount)
+        },
+        Ok(Cw20HookMsg::Redeem {}) => {
+            // only receipt token contract can execute this message
+            let contract_addr = info.sender;
+            let config: Config = CONFIG.load(deps.storage)?;
+            if Some(contract_addr) != config.receipt_token {
+                return Err(ContractError::Unauthorized {});
+            }
+            let cw20_sender_addr = deps.api.addr_validate(&cw20_msg.sender)?;
+            try_redeem(deps, env, cw20_sender_addr, cw20_msg.amount)
+        },
         _ => Err(ContractError::MissingDepositHook {}),
     }
 }
 
-pub fn try_deposit(deps: DepsMut, user_addr: Addr, amount: Uint128) -> Result<Response, ContractError> {
-    USER_INFO.update(
+pub fn try_deposit(deps: DepsMut, env: Env, token: String, user_addr: Addr, amount: Uint128) -> Result<Response, ContractError> {
+    ensure_not_paused(PAUSE_STATE.load(deps.storage)?.deposit, "deposit")?;
+    accrue_interest(deps.storage, &env)?;
+    let mut collateral = COLLATERAL_STATE.load(deps.storage, &token)?;
+    let receipts = collateral.receipts_for(amount);
+    if receipts.is_zero() {
+        return Err(ContractError::InsufficientFunds {});
+    }
+    let user_data = USER_INFO.update(
         deps.storage,
         &user_addr,
         |ud: Option<UserData>| -> StdResult<_> { 
             match ud {
-                Some(user_data) => Ok(user_data.deposit_amount(amount)),
-                None => Ok (UserData::new().deposit_amount(amount))
+                Some(user_data) => Ok(user_data.deposit_amount(&token, receipts)),
+                None => Ok (UserData::new().deposit_amount(&token, receipts))
             }
         },
     )?;
-    Ok(Response::default())
+    collateral.total_deposits += amount;
+    collateral.total_receipts += receipts;
+    if let Some(cap) = MARKETS.load(deps.storage, &token)?.supply_cap {
+        if collateral.total_deposits > cap {
+            let remaining = cap.saturating_sub(collateral.total_deposits - amount);
+            return Err(ContractError::SupplyCapExceeded { token, cap, remaining });
+        }
+    }
+    COLLATERAL_STATE.save(deps.storage, &token, &collateral)?;
+    let deposited = collateral.underlying_for(user_data.deposited(&token));
+    Ok(Response::new()
+        .add_attribute("action", "deposit")
+        .add_attribute("receipts", receipts.to_string())
+        .add_event(events::deposit(&user_addr, &token, amount, deposited))
+    )
+}
+
+/// Deposits every coin sent with the message, each into its native market
+pub fn try_deposit_native(mut deps: DepsMut, env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+    if info.funds.is_empty() {
+        return Err(ContractError::InsufficientFunds {});
+    }
+    let mut response = Response::new().add_attribute("action", "deposit");
+    for coin in info.funds {
+        match MARKETS.may_load(deps.storage, &coin.denom)? {
+            Some(market) if market.native => {},
+            _ => return Err(ContractError::UnknownDenom { denom: coin.denom }),
+        }
+        let res = try_deposit(deps.branch(), env.clone(), coin.denom, info.sender.clone(), coin.amount)?;
+        response = response.add_events(res.events);
+    }
+    Ok(response)
+}
+
+/// Repays `user_addr`'s loans with `payer`'s payment, `loan_id` alone when
+/// given, otherwise in the order of `policy`, and refunds whatever is left
+/// over to `payer`
+pub fn try_payoff(
+    mut deps: DepsMut,
+    env: Env,
+    payer: Addr,
+    user_addr: Addr,
+    amount: Uint128,
+    loan_id: Option<u128>,
+    policy: RepaymentPolicy,
+) -> Result<Response, ContractError> {
+    let (repayment, messages) = payoff(deps.branch(), &env, &payer, &user_addr, amount, loan_id, policy)?;
+    let debt = get_total_owed(deps.storage, &env, &user_addr)?;
+    let repay_events = events::repay(&user_addr, &payer, &repayment.allocations, debt);
+
+    let mut attributes = vec![
+        attr("action", "payoff"),
+        attr("payer", payer.to_string()),
+        attr("borrower", user_addr.to_string()),
+        attr("amount", (amount - repayment.leftover).to_string()),
+        attr("refund_amount", repayment.leftover.to_string()),
+    ];
+    for (loan_id, paid) in repayment.allocations {
+        attributes.push(attr(format!("loan_{}", loan_id), paid.to_string()));
+    }
+    Ok(Response::new().add_messages(messages).add_attributes(attributes).add_events(repay_events))
+}
+
+/// Applies and settles a payment, returning how it was split along with the
+/// refund and token messages
+pub(crate) fn payoff(
+    deps: DepsMut,
+    env: &Env,
+    payer: &Addr,
+    user_addr: &Addr,
+    amount: Uint128,
+    loan_id: Option<u128>,
+    policy: RepaymentPolicy,
+) -> Result<(Repayment, Vec<CosmosMsg>), ContractError> {
+    ensure_not_paused(PAUSE_STATE.load(deps.storage)?.payoff, "payoff")?;
+    let config = CONFIG.load(deps.storage)?;
+    let lending_token = lending_token_addr(&config)?;
+    let mut state = accrue_interest(deps.storage, env)?;
+    let mut repayment = Repayment { leftover: amount, interest: Uint128::zero(), allocations: vec![] };
+    match USER_INFO.may_load(deps.storage, user_addr)? {
+        Some(mut user_data) => {
+            repayment = apply_repayment(deps.storage, user_addr, &mut user_data, &mut state, amount, loan_id, policy)?;
+            USER_INFO.save(deps.storage, user_addr, &user_data)?;
+        },
+        None => if let Some(loan_id) = loan_id {
+            return Err(ContractError::LoanNotFound { loan_id });
+        },
+    }
+
+    let mut messages = vec![];
+    // if leftovers exist, return to payer
+    if repayment.leftover > Uint128::zero() {
+        messages.push(Cw20Contract(lending_token.clone()).call(
+            Cw20ExecuteMsg::Transfer { recipient: payer.to_string(), amount: repayment.leftover }
+        )?);
+    }
+    messages.extend(settle_repayment(
+        env,
+        &config,
+        &mut state,
+        payer,
+        amount - repayment.leftover,
+        repayment.interest,
+        false,
+    )?);
+    MARKET_STATE.save(deps.storage, &state)?;
+    Ok((repayment, messages))
 }
 
-pub fn try_payoff(deps: DepsMut, user_addr: Addr, env: Env, amount: Uint128) -> Result<Response, ContractError> {
+/// Outcome of applying a payment to a user's loans
+pub(crate) struct Repayment {
+    /// part of the payment left over once every loan is closed
+    pub leftover: Uint128,
+    /// part of the payment that covered accrued interest
+    pub interest: Uint128,
+    /// amount applied to each loan, by loan id
+    pub allocations: Vec<(u128, Uint128)>,
+}
+
+/// Applies `amount` to the user's loans, closing loans that are fully
+/// repaid, and removes the repaid debt from `user_data` and `state`. Only
+/// `loan_id` is repaid when given, otherwise loans are repaid in the order of
+/// `policy`. Each payment covers a loan's accrued interest before its
+/// principal. `state` must be accrued up to the current block time.
+fn apply_repayment(
+    storage: &mut dyn Storage,
+    user_addr: &Addr,
+    user_data: &mut UserData,
+    state: &mut MarketState,
+    amount: Uint128,
+    loan_id: Option<u128>,
+    policy: RepaymentPolicy,
+) -> Result<Repayment, ContractError> {
     let mut payoff_amount = amount;
-    let loans: StdResult<Vec<_>> = LOANS.prefix(&user_addr).range(deps.storage, None, None, Order::Ascending).collect();
-    for (loan_id, loan_info) in loans.unwrap() {
-        let updated_loan_info = loan_info.update_loan(env.block.time);
-        let mut principal = updated_loan_info.principal.atomics();
-        let mut amount_owed = updated_loan_info.amount_owed.atomics();
-        if payoff_amount <= amount_owed {
-            principal = amount_owed - payoff_amount;
-            amount_owed -= payoff_amount;
-            if amount_owed == Uint128::zero() {
-                LOANS.remove(deps.storage, (&user_addr, loan_id));
+    let mut interest = Uint128::zero();
+    let mut allocations = vec![];
+    let mut scaled_repaid = Uint128::zero();
+    let mut loans: Vec<(u128, LoanInfo)> = match loan_id {
+        Some(loan_id) => match LOANS.may_load(storage, (user_addr, loan_id))? {
+            Some(loan_info) => vec![(loan_id, loan_info)],
+            None => return Err(ContractError::LoanNotFound { loan_id }),
+        },
+        None => LOANS.prefix(user_addr).range(storage, None, None, Order::Ascending).collect::<StdResult<_>>()?,
+    };
+    for (_, loan_info) in loans.iter_mut() {
+        *loan_info = loan_info.update_loan(state.last_accrual_time);
+    }
+    match policy {
+        RepaymentPolicy::OldestFirst => {},
+        RepaymentPolicy::NewestFirst => loans.reverse(),
+        // stable sort, equal balances stay oldest first
+        RepaymentPolicy::HighestBalanceFirst => {
+            loans.sort_by_key(|(_, loan_info)| std::cmp::Reverse(loan_info.amount_owed(state)))
+        },
+    }
+
+    for (loan_id, loan_info) in loans {
+        let amount_owed = loan_info.amount_owed(state);
+        let interest_owed = amount_owed.saturating_sub(loan_info.principal);
+        if payoff_amount < amount_owed {
+            let interest_paid = std::cmp::min(payoff_amount, interest_owed);
+            let principal_paid = payoff_amount - interest_paid;
+            interest += interest_paid;
+            let loan_info = if let Some(term) = &loan_info.term {
+                state.total_term_principal -= principal_paid;
+                state.term_interest_per_year = state.term_interest_per_year.saturating_sub(principal_paid * term.rate);
+                LoanInfo {
+                    principal: loan_info.principal - principal_paid,
+                    interest: loan_info.interest - interest_paid,
+                    ..loan_info
+                }
             } else {
-                LOANS.save(deps.storage, (&user_addr, loan_id), &LoanInfo{ 
-                    start_time: updated_loan_info.start_time, 
-                    last_update_time: env.block.time, 
-                    principal: Decimal::new(principal), 
-                    amount_owed: Decimal::new(amount_owed) 
-                })?;
-            }
+                let scaled = state.scale_repayment(payoff_amount);
+                scaled_repaid += scaled;
+                LoanInfo {
+                    principal: loan_info.principal - principal_paid,
+                    scaled_debt: loan_info.scaled_debt - scaled,
+                    ..loan_info
+                }
+            };
+            LOANS.save(storage, (user_addr, loan_id), &loan_info)?;
+            allocations.push((loan_id, payoff_amount));
+            payoff_amount = Uint128::zero();
             break;
+        }
+        payoff_amount -= amount_owed;
+        interest += interest_owed;
+        allocations.push((loan_id, amount_owed));
+        if let Some(term) = &loan_info.term {
+            state.total_term_principal -= loan_info.principal;
+            state.term_interest_per_year = state.term_interest_per_year.saturating_sub(loan_info.principal * term.rate);
+            TERM_LOANS.remove(storage, (user_addr, loan_id));
         } else {
-            payoff_amount -= amount_owed;
-            LOANS.remove(deps.storage, (&user_addr, loan_id));
+            scaled_repaid += loan_info.scaled_debt;
+        }
+        LOANS.remove(storage, (user_addr, loan_id));
+        if payoff_amount.is_zero() {
+            break;
         }
     }
+    *user_data = user_data.repay_scaled(scaled_repaid);
+    state.total_scaled_debt -= scaled_repaid;
+    Ok(Repayment { leftover: payoff_amount, interest, allocations })
+}
+
+/// Disposes of `repaid` lending tokens: principal refills what the pool lent
+/// out and interest pays what was credited to suppliers and reserves as it
+/// accrued, both back into the pool's cash. Everything else is burned.
+///
+/// When `from_allowance` is set the tokens are pulled from `payer`'s
+/// allowance, otherwise this contract already holds them.
+fn settle_repayment(
+    env: &Env,
+    config: &Config,
+    state: &mut MarketState,
+    payer: &Addr,
+    repaid: Uint128,
+    interest: Uint128,
+    from_allowance: bool,
+) -> Result<Vec<CosmosMsg>, ContractError> {
+    let refilled = std::cmp::min(repaid - interest, state.total_lent);
+    let interest_kept = std::cmp::min(interest, state.interest_receivable);
+    state.total_lent -= refilled;
+    state.interest_receivable -= interest_kept;
+    let kept_amount = refilled + interest_kept;
+    state.total_cash += kept_amount;
+    let burn_amount = repaid - kept_amount;
 
-    // if leftovers exist, return to user
+    let token = Cw20Contract(lending_token_addr(config)?);
+    let mut messages = vec![];
+    if from_allowance {
+        if !kept_amount.is_zero() {
+            messages.push(token.call(Cw20ExecuteMsg::TransferFrom {
+                owner: payer.to_string(),
+                recipient: env.contract.address.to_string(),
+                amount: kept_amount,
+            })?);
+        }
+        if !burn_amount.is_zero() {
+            messages.push(token.call(Cw20ExecuteMsg::BurnFrom { owner: payer.to_string(), amount: burn_amount })?);
+        }
+    } else if !burn_amount.is_zero() {
+        messages.push(token.call(Cw20ExecuteMsg::Burn { amount: burn_amount })?);
+    }
+    Ok(messages)
+}
+
+/// Adds lending tokens to the supply pool and mints receipts at the current
+/// exchange rate
+pub fn try_supply(deps: DepsMut, env: Env, supplier: Addr, amount: Uint128) -> Result<Response, ContractError> {
     let config = CONFIG.load(deps.storage)?;
-    if payoff_amount > Uint128::zero() {
-        let transfer_response = Cw20Contract(config.lending_token.clone().unwrap()).call(
-            Cw20ExecuteMsg::Transfer { recipient: user_addr.to_string(), amount: payoff_amount }
-        )?;
-        let burn_msg = Cw20Contract(config.lending_token.clone().unwrap()).call(
-            Cw20ExecuteMsg::Burn { amount: amount.checked_sub(payoff_amount).unwrap() }
-        )?;
-        return Ok(Response::new().add_messages(vec![transfer_response, burn_msg]));
-    }
-    let burn_msg = Cw20Contract(config.lending_token.clone().unwrap()).call(
-        Cw20ExecuteMsg::Burn { amount: amount }
+    let mut state = accrue_interest(deps.storage, &env)?;
+    let receipts = state.receipts_for(amount);
+    if receipts.is_zero() {
+        return Err(ContractError::InsufficientFunds {});
+    }
+    state.total_supply += amount;
+    state.total_receipts += receipts;
+    state.total_cash += amount;
+    MARKET_STATE.save(deps.storage, &state)?;
+
+    let mint_msg = Cw20Contract(receipt_token_addr(&config)?).call(
+        Cw20ExecuteMsg::Mint { recipient: supplier.to_string(), amount: receipts }
     )?;
-    Ok(Response::new().add_message(burn_msg))
+    Ok(Response::new().add_message(mint_msg).add_attributes(vec![
+        attr("action", "supply"),
+        attr("supplier", supplier.to_string()),
+        attr("amount", amount.to_string()),
+        attr("receipts", receipts.to_string()),
+    ]))
+}
+
+/// Burns receipts sent to this contract and pays out their share of the
+/// supply pool, minting what is lent out
+pub fn try_redeem(deps: DepsMut, env: Env, redeemer: Addr, receipts: Uint128) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let mut state = accrue_interest(deps.storage, &env)?;
+    if receipts > state.total_receipts {
+        return Err(ContractError::InsufficientFunds {});
+    }
+    let amount = state.underlying_for(receipts);
+    state.total_supply -= amount;
+    state.total_receipts -= receipts;
+    let minted = state.pay_out(amount);
+    MARKET_STATE.save(deps.storage, &state)?;
+
+    let burn_msg = Cw20Contract(receipt_token_addr(&config)?).call(
+        Cw20ExecuteMsg::Burn { amount: receipts }
+    )?;
+    let mut messages = vec![burn_msg];
+    messages.extend(pay_lending_tokens(&lending_token_addr(&config)?, &redeemer, amount, minted)?);
+    Ok(Response::new().add_messages(messages).add_attributes(vec![
+        attr("action", "redeem"),
+        attr("redeemer", redeemer.to_string()),
+        attr("receipts", receipts.to_string()),
+        attr("amount", amount.to_string()),
+    ]))
+}
+
+/// Address of the lending token, set by the admin after instantiation
+fn lending_token_addr(config: &Config) -> Result<Addr, ContractError> {
+    config.lending_token.clone().ok_or(ContractError::TokenNotSet { token: "lending".to_string() })
+}
+
+/// Address of the supply receipt token, set by the admin after instantiation
+fn receipt_token_addr(config: &Config) -> Result<Addr, ContractError> {
+    config.receipt_token.clone().ok_or(ContractError::TokenNotSet { token: "receipt".to_string() })
+}
+
+/// Sends `amount` lending tokens to `recipient`, transferring them from this
+/// contract except for the `minted` part
+fn pay_lending_tokens(lending_token: &Addr, recipient: &Addr, amount: Uint128, minted: Uint128) -> StdResult<Vec<CosmosMsg>> {
+    let token = Cw20Contract(lending_token.clone());
+    let mut messages = vec![];
+    if amount > minted {
+        messages.push(token.call(Cw20ExecuteMsg::Transfer { recipient: recipient.to_string(), amount: amount - minted })?);
+    }
+    if !minted.is_zero() {
+        messages.push(token.call(Cw20ExecuteMsg::Mint { recipient: recipient.to_string(), amount: minted })?);
+    }
+    Ok(messages)
 }
 
-/// Ensure user exists, and subtract from deposit
+/// Repays part of an undercollateralized borrower's debt on behalf of the
+/// liquidator and transfers them the equivalent `collateral` tokens plus the
+/// collateral market's liquidation bonus.
 /// 
-/// TODO (do after borrowing is implemented):
-///     Must ensure that the user is still liquid after withdrawal 
-///     ie Borrow amount must not exceed deposited amount
-pub fn try_withdraw(deps: DepsMut, info: MessageInfo, env: Env, withdraw_amount: Uint128) -> Result<Response, ContractError>{
+/// When `from_allowance` is set the repaid lending tokens are burned from the
+/// liquidator's allowance, otherwise they have already been sent to this
+/// contract through the cw20 receive hook and any excess is refunded.
+pub fn try_liquidate(
+    deps: DepsMut,
+    env: Env,
+    liquidator: Addr,
+    borrower: Addr,
+    collateral: String,
+    amount: Uint128,
+    from_allowance: bool,
+) -> Result<Response, ContractError> {
+    ensure_not_paused(PAUSE_STATE.load(deps.storage)?.liquidate, "liquidate")?;
+    let config = CONFIG.load(deps.storage)?;
+    let lending_token = lending_token_addr(&config)?;
+    let mut user_data = match USER_INFO.may_load(deps.storage, &borrower)? {
+        Some(user_data) => user_data,
+        None => return Err(ContractError::UserDNE {}),
+    };
+    let deposited = underlying_deposit(deps.storage, &user_data, &collateral)?;
+    if deposited.is_zero() {
+        return Err(ContractError::InsufficientFunds {});
+    }
+    if !user_data.is_collateral(&collateral) {
+        return Err(ContractError::CollateralDisabled { token: collateral });
+    }
+
+    let deposits = deposit_amounts(deps.storage, &user_data.collateral())?;
+    let account = account_value(deps.storage, &deps.querier, &env, &config, &deposits)?;
+    let mut state = accrue_interest(deps.storage, &env)?;
+    cache_prices(deps.storage, &account.prices)?;
+    let amount_owed = user_debt(deps.storage, &state, &borrower, &user_data)?;
+    if amount_owed <= account.liquidation_limit {
+        return Err(ContractError::NotLiquidatable {});
+    }
+
+    // a single liquidation may only close part of the debt
+    let market = MARKETS.load(deps.storage, &collateral)?;
+    let price = account.price(&collateral);
+    let repay_amount = std::cmp::min(amount, amount_owed * config.close_factor);
+    let seize_amount = std::cmp::min(
+        from_value(repay_amount * (Decimal::one() + market.liquidation_bonus), price),
+        deposited,
+    );
+
+    let repayment = apply_repayment(
+        deps.storage,
+        &borrower,
+        &mut user_data,
+        &mut state,
+        repay_amount,
+        None,
+        RepaymentPolicy::OldestFirst,
+    )?;
+    let mut collateral_state = COLLATERAL_STATE.load(deps.storage, &collateral)?;
+    let seized_receipts = std::cmp::min(collateral_state.receipts_to_withdraw(seize_amount), user_data.deposited(&collateral));
+    let user_data = user_data.withdraw_amount(&collateral, seized_receipts);
+    USER_INFO.save(deps.storage, &borrower, &user_data)?;
+    let debt = user_debt(deps.storage, &state, &borrower, &user_data)?;
+    collateral_state.total_deposits -= seize_amount;
+    collateral_state.total_receipts -= seized_receipts;
+    COLLATERAL_STATE.save(deps.storage, &collateral, &collateral_state)?;
+
+    let mut messages = vec![];
+    let refund_amount = amount - repay_amount;
+    if !from_allowance && !refund_amount.is_zero() {
+        messages.push(Cw20Contract(lending_token.clone()).call(
+            Cw20ExecuteMsg::Transfer { recipient: liquidator.to_string(), amount: refund_amount }
+        )?);
+    }
+    messages.extend(settle_repayment(
+        &env,
+        &config,
+        &mut state,
+        &liquidator,
+        repay_amount,
+        repayment.interest,
+        from_allowance,
+    )?);
+    MARKET_STATE.save(deps.storage, &state)?;
+    messages.push(transfer_collateral(&collateral, &market, &liquidator, seize_amount)?);
+    let mut liquidation_events = events::repay(&borrower, &liquidator, &repayment.allocations, debt);
+    let deposited = collateral_state.underlying_for(user_data.deposited(&collateral));
+    liquidation_events.push(events::withdraw(&borrower, &liquidator, &collateral, seize_amount, deposited, debt));
+
+    Ok(Response::new().add_messages(messages).add_events(liquidation_events).add_attributes(vec![
+        attr("action", "liquidate"),
+        attr("liquidator", liquidator.to_string()),
+        attr("borrower", borrower.to_string()),
+        attr("collateral", collateral),
+        attr("repay_amount", repay_amount.to_string()),
+        attr("seize_amount", seize_amount.to_string()),
+        attr("price", price.to_string()),
+        attr("refund_amount", if from_allowance { Uint128::zero() } else { refund_amount }.to_string()),
+    ]))
+}
+
+/// Ensure user exists, and subtract from their deposit of `token`
+/// 
+/// The remaining deposits must still cover the user's debt at their
+/// collateral factors, ie the withdrawal may not take the user over their
+/// borrow limit. Users without debt can withdraw without an oracle price.
+pub fn try_withdraw(
+    deps: DepsMut,
+    info: MessageInfo,
+    env: Env,
+    token: String,
+    withdraw_amount: Uint128,
+) -> Result<Response, ContractError> {
+    let paused = PAUSE_STATE.load(deps.storage)?;
+    ensure_not_paused(paused.withdraw, "withdraw")?;
     let value = USER_INFO.may_load(deps.storage, &info.sender).unwrap();
     match value {
         Some(user_data) => {
-            let deposit_amount = user_data.generic_token_deposited;
-            let amount_owed = get_total_owed(deps.storage, env, info.clone().sender);
-            if withdraw_amount > deposit_amount {
-                return Err(ContractError::InsufficientFunds {  });
-            } else if amount_owed < deposit_amount && withdraw_amount > (deposit_amount - amount_owed) {
+            let config = CONFIG.load(deps.storage)?;
+            if withdraw_amount > underlying_deposit(deps.storage, &user_data, &token)? {
                 return Err(ContractError::InsufficientFunds {  });
             }
-            USER_INFO.save(deps.storage, &info.sender, &user_data.withdraw_amount(withdraw_amount))?;
-            let config = CONFIG.load(deps.storage)?;
-            let transfer_response = Cw20Contract(config.generic_token).call(
-                Cw20ExecuteMsg::Transfer { recipient: info.sender.to_string(), amount: withdraw_amount }
-            )?;
-            return Ok(Response::new().add_message(transfer_response).add_attributes(vec![
+            // the remaining deposits are valued at the market's new totals
+            let mut collateral = COLLATERAL_STATE.load(deps.storage, &token)?;
+            let receipts = std::cmp::min(collateral.receipts_to_withdraw(withdraw_amount), user_data.deposited(&token));
+            collateral.total_deposits -= withdraw_amount;
+            collateral.total_receipts -= receipts;
+            COLLATERAL_STATE.save(deps.storage, &token, &collateral)?;
+            let user_data = user_data.withdraw_amount(&token, receipts);
+            let state = accrue_interest(deps.storage, &env)?;
+            let amount_owed = user_debt(deps.storage, &state, &info.sender, &user_data)?;
+            if !amount_owed.is_zero() {
+                // withdrawing against debt relies on the oracle as borrowing does
+                ensure_not_paused(paused.borrow, "borrow")?;
+                let deposits = deposit_amounts(deps.storage, &user_data.collateral())?;
+                let account = account_value(deps.storage, &deps.querier, &env, &config, &deposits)?;
+                if amount_owed > account.borrow_limit {
+                    return Err(ContractError::InsufficientFunds {  });
+                }
+                cache_prices(deps.storage, &account.prices)?;
+            }
+            USER_INFO.save(deps.storage, &info.sender, &user_data)?;
+            let market = MARKETS.load(deps.storage, &token)?;
+            let transfer_response = transfer_collateral(&token, &market, &info.sender, withdraw_amount)?;
+            let event = events::withdraw(
+                &info.sender,
+                &info.sender,
+                &token,
+                withdraw_amount,
+                collateral.underlying_for(user_data.deposited(&token)),
+                amount_owed,
+            );
+            Ok(Response::new().add_message(transfer_response).add_event(event).add_attributes(vec![
                 attr("action", "withdraw"),
                 attr("withdrawer", info.sender.to_string()),
+                attr("token", token),
                 attr("amount", withdraw_amount.to_string()),
             ]))
         },
-        None => return Err(ContractError::UserDNE { })
-    };
+        None => Err(ContractError::UserDNE { })
+    }
 }
 
-pub fn try_borrow(deps: DepsMut, info: MessageInfo, env: Env, borrow_amount: Uint128) -> Result<Response, ContractError>{
-    let mint_response;
-    let value = USER_INFO.may_load(deps.storage, &info.sender).unwrap();
+/// Switches deposits of `token` between collateral and supply only. Taking
+/// them out of collateral is checked against the borrow limit as a
+/// withdrawal of the whole deposit would be.
+pub fn try_set_collateral(
+    deps: DepsMut,
+    info: MessageInfo,
+    env: Env,
+    token: String,
+    enabled: bool,
+) -> Result<Response, ContractError> {
+    let user_data = USER_INFO.may_load(deps.storage, &info.sender)?.ok_or(ContractError::UserDNE {})?;
+    MARKETS.load(deps.storage, &token)?;
+    let user_data = user_data.set_collateral(&token, enabled);
+    if !enabled {
+        let paused = PAUSE_STATE.load(deps.storage)?;
+        ensure_not_paused(paused.withdraw, "withdraw")?;
+        let state = accrue_interest(deps.storage, &env)?;
+        let amount_owed = user_debt(deps.storage, &state, &info.sender, &user_data)?;
+        if !amount_owed.is_zero() {
+            ensure_not_paused(paused.borrow, "borrow")?;
+            let config = CONFIG.load(deps.storage)?;
+            let deposits = deposit_amounts(deps.storage, &user_data.collateral())?;
+            let account = account_value(deps.storage, &deps.querier, &env, &config, &deposits)?;
+            if amount_owed > account.borrow_limit {
+                return Err(ContractError::InsufficientFunds {});
+            }
+            cache_prices(deps.storage, &account.prices)?;
+        }
+    }
+    USER_INFO.save(deps.storage, &info.sender, &user_data)?;
+    Ok(Response::new().add_attributes(vec![
+        attr("action", "set_collateral"),
+        attr("user", info.sender.to_string()),
+        attr("token", token),
+        attr("enabled", enabled.to_string()),
+    ]))
+}
+
+/// Sets what `delegate` may borrow against the sender's collateral, a zero `limit` revokes it
+pub fn approve_borrow_delegate(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    delegate: String,
+    limit: Uint128,
+    expires: Option<Expiration>,
+) -> Result<Response, ContractError> {
+    let delegate_addr = deps.api.addr_validate(&delegate)?;
+    if delegate_addr == info.sender {
+        return Err(ContractError::CannotDelegateToSelf {});
+    }
+    let expires = expires.unwrap_or_default();
+    if expires.is_expired(&env.block) {
+        return Err(ContractError::DelegationExpired {});
+    }
+    let key = (&info.sender, &delegate_addr);
+    if limit.is_zero() {
+        BORROW_DELEGATIONS.remove(deps.storage, key);
+    } else {
+        BORROW_DELEGATIONS.save(deps.storage, key, &BorrowDelegation { limit, expires })?;
+    }
+    Ok(Response::new().add_attributes(vec![
+        attr("action", "approve_borrow_delegate"),
+        attr("owner", info.sender.to_string()),
+        attr("delegate", delegate_addr.to_string()),
+        attr("limit", limit.to_string()),
+    ]))
+}
+
+/// Spends `amount` of the delegation `owner` approved for `delegate`
+fn use_borrow_delegation(
+    storage: &mut dyn Storage,
+    env: &Env,
+    owner: &Addr,
+    delegate: &Addr,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let key = (owner, delegate);
+    let mut delegation = BORROW_DELEGATIONS.may_load(storage, key)?
+        .ok_or(ContractError::Unauthorized {})?;
+    if delegation.expires.is_expired(&env.block) {
+        return Err(ContractError::DelegationExpired {});
+    }
+    delegation.limit = delegation.limit.checked_sub(amount)
+        .map_err(|_| ContractError::DelegationExceeded { limit: delegation.limit })?;
+    if delegation.limit.is_zero() {
+        BORROW_DELEGATIONS.remove(storage, key);
+    } else {
+        BORROW_DELEGATIONS.save(storage, key, &delegation)?;
+    }
+    Ok(())
+}
+
+/// Borrows `borrow_amount` against the collateral of `on_behalf_of`, or of the
+/// sender when it is not given, and sends the lending tokens to the sender,
+/// out of the supply pool's cash first and minted for the rest. With a `term`
+/// the loan matures after that many seconds and accrues at the term loan rate
+/// in place of the borrow index.
+pub fn try_borrow(
+    deps: DepsMut,
+    info: MessageInfo,
+    env: Env,
+    borrow_amount: Uint128,
+    term: Option<u64>,
+    on_behalf_of: Option<Addr>,
+) -> Result<Response, ContractError>{
+    ensure_not_paused(PAUSE_STATE.load(deps.storage)?.borrow, "borrow")?;
+    if term == Some(0) {
+        return Err(ContractError::InvalidTerm {});
+    }
+    let borrower = on_behalf_of.unwrap_or_else(|| info.sender.clone());
+    if borrower != info.sender {
+        use_borrow_delegation(deps.storage, &env, &borrower, &info.sender, borrow_amount)?;
+    }
+    let loan_messages;
+    let collateral_value;
+    let borrow_event;
+    let value = USER_INFO.may_load(deps.storage, &borrower).unwrap();
     match value {
         Some(user_data) => {
-            if borrow_amount > (user_data.generic_token_deposited - user_data.borrow_amt) {
+            let config = CONFIG.load(deps.storage)?;
+            let deposits = deposit_amounts(deps.storage, &user_data.collateral())?;
+            let account = account_value(deps.storage, &deps.querier, &env, &config, &deposits)?;
+            collateral_value = account.collateral_value;
+            let mut state = accrue_interest(deps.storage, &env)?;
+            cache_prices(deps.storage, &account.prices)?;
+            let amount_owed = user_debt(deps.storage, &state, &borrower, &user_data)?;
+            if amount_owed + borrow_amount > account.borrow_limit {
                 return Err(ContractError::InsufficientFunds {  });
             }
-            // mint lending token and send to borrower
-            let config = CONFIG.load(deps.storage)?;
-            mint_response = Cw20Contract(config.lending_token.unwrap()).call(
-                Cw20ExecuteMsg::Mint { 
-                    recipient: info.sender.to_string(), 
-                    amount: borrow_amount
+            if let Some(cap) = config.borrow_cap {
+                let total_borrows = state.total_borrows();
+                if total_borrows + borrow_amount > cap {
+                    return Err(ContractError::BorrowCapExceeded { cap, remaining: cap.saturating_sub(total_borrows) });
                 }
-            )?;
+            }
+            // lend from the pool and mint the rest
+            let lent = std::cmp::min(borrow_amount, state.total_cash);
+            state.total_cash -= lent;
+            state.total_lent += lent;
+            loan_messages = pay_lending_tokens(&lending_token_addr(&config)?, &info.sender, borrow_amount, borrow_amount - lent)?;
             
             // create and save loan
             let loan_id = user_data.curr_loan_id;
-            let loan_info = LoanInfo::new(env.block.time, borrow_amount);
-            LOANS.save(deps.storage, (&info.sender, loan_id.u128()), &loan_info)?;
-            USER_INFO.save(deps.storage, &info.sender, &user_data.borrow_amount(borrow_amount))?;
+            let loan_info = match term {
+                Some(term) => {
+                    state.total_term_principal += borrow_amount;
+                    state.term_interest_per_year += borrow_amount * config.term_loan_rate;
+                    LoanInfo::new_term(env.block.time, borrow_amount, LoanTerm {
+                        maturity: env.block.time.plus_seconds(term),
+                        rate: config.term_loan_rate,
+                        penalty_rate: config.penalty_rate,
+                        grace_period: config.grace_period,
+                    })
+                },
+                None => {
+                    let scaled_debt = state.scale(borrow_amount);
+                    state.total_scaled_debt += scaled_debt;
+                    LoanInfo::new(env.block.time, borrow_amount, scaled_debt)
+                },
+            };
+            LOANS.save(deps.storage, (&borrower, loan_id.u128()), &loan_info)?;
+            if loan_info.term.is_some() {
+                TERM_LOANS.save(deps.storage, (&borrower, loan_id.u128()), &Empty {})?;
+            }
+            USER_INFO.save(deps.storage, &borrower, &user_data.borrow_amount(borrow_amount, loan_info.scaled_debt))?;
+            MARKET_STATE.save(deps.storage, &state)?;
+            borrow_event = events::borrow(&borrower, &info.sender, loan_id, borrow_amount, amount_owed + borrow_amount);
         },
         None => return Err(ContractError::UserDNE { })
     }
     Ok(Response::new()
-        .add_message(mint_response)
+        .add_messages(loan_messages)
+        .add_event(borrow_event)
         .add_attributes(vec![
             attr("action", "borrow"),
-            attr("borrower", info.sender.to_string()),
+            attr("borrower", borrower.to_string()),
+            attr("recipient", info.sender.to_string()),
             attr("amount", borrow_amount.to_string()),
+            attr("collateral_value", collateral_value.to_string()),
         ])
     )
 }
 
-pub fn set_lending_token_addr(deps: DepsMut, info: MessageInfo, address: String) -> Result<Response, ContractError> {
-    let config = CONFIG.load(deps.storage).unwrap();
-    if info.sender != config.admin {
+pub fn try_flash_loan(
+    deps: DepsMut,
+    env: Env,
+    token: String,
+    amount: Uint128,
+    receiver: String,
+    msg: Binary,
+) -> Result<Response, ContractError> {
+    ensure_not_paused(PAUSE_STATE.load(deps.storage)?.borrow, "borrow")?;
+    if FLASH_LOAN.may_load(deps.storage)?.is_some() {
+        return Err(ContractError::FlashLoanInProgress {});
+    }
+    let config = CONFIG.load(deps.storage)?;
+    let market = MARKETS.load(deps.storage, &token)?;
+    let collateral = COLLATERAL_STATE.load(deps.storage, &token)?;
+    if amount.is_zero() || amount > collateral.total_deposits {
+        return Err(ContractError::InsufficientFunds {});
+    }
+    let receiver_addr = deps.api.addr_validate(&receiver)?;
+
+    let loan = FlashLoanState {
+        balance_before: collateral_balance(&deps.querier, &env, &token, &market)?,
+        deposits_before: collateral.total_deposits,
+        fee: amount * config.flash_loan_fee,
+        token,
+    };
+    FLASH_LOAN.save(deps.storage, &loan)?;
+
+    // the callback runs once the loan is sent, repayment is checked in `reply`
+    let transfer = transfer_collateral(&loan.token, &market, &receiver_addr, amount)?;
+    let callback = WasmMsg::Execute {
+        contract_addr: receiver_addr.to_string(),
+        msg: to_binary(&FlashLoanReceiverMsg::FlashLoanCallback { amount, fee: loan.fee, msg })?,
+        funds: vec![],
+    };
+    Ok(Response::new()
+        .add_message(transfer)
+        .add_submessage(SubMsg::reply_on_success(callback, FLASH_LOAN_REPLY_ID))
+        .add_attributes(vec![
+            attr("action", "flash_loan"),
+            attr("token", loan.token),
+            attr("receiver", receiver_addr.to_string()),
+            attr("amount", amount.to_string()),
+            attr("fee", loan.fee.to_string()),
+        ])
+    )
+}
+
+/// Checks the flash loan in progress came back with its fee and credits the
+/// fee to the market's deposits, less the reserve factor's share which is
+/// sent to the treasury
+fn settle_flash_loan(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let loan = FLASH_LOAN.load(deps.storage)?;
+    FLASH_LOAN.remove(deps.storage);
+    let config = CONFIG.load(deps.storage)?;
+    let market = MARKETS.load(deps.storage, &loan.token)?;
+    let balance = collateral_balance(&deps.querier, &env, &loan.token, &market)?;
+    let mut collateral = COLLATERAL_STATE.load(deps.storage, &loan.token)?;
+    if balance + loan.deposits_before < loan.balance_before + collateral.total_deposits + loan.fee {
+        return Err(ContractError::FlashLoanNotRepaid {});
+    }
+
+    // with no receipts out there is no one to earn the depositors' share
+    let treasury_fee = if collateral.total_receipts.is_zero() { loan.fee } else { loan.fee * config.reserve_factor };
+    let depositor_fee = loan.fee - treasury_fee;
+    collateral.total_deposits += depositor_fee;
+    COLLATERAL_STATE.save(deps.storage, &loan.token, &collateral)?;
+
+    let mut response = Response::new().add_attributes(vec![
+        attr("action", "settle_flash_loan"),
+        attr("token", loan.token.clone()),
+        attr("fee", loan.fee.to_string()),
+        attr("depositor_fee", depositor_fee.to_string()),
+        attr("treasury_fee", treasury_fee.to_string()),
+    ]);
+    if !treasury_fee.is_zero() {
+        response = response.add_message(transfer_collateral(&loan.token, &market, &config.treasury, treasury_fee)?);
+    }
+    Ok(response)
+}
+
+pub fn propose_admin(deps: DepsMut, info: MessageInfo, address: String) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    ensure_admin(&config, &info.sender)?;
+    let pending_admin = deps.api.addr_validate(&address)?;
+    config.pending_admin = Some(pending_admin.clone());
+    CONFIG.save(deps.storage, &config)?;
+    Ok(Response::new().add_attributes(vec![
+        attr("action", "propose_admin"),
+        attr("pending_admin", pending_admin.to_string()),
+    ]))
+}
+
+pub fn accept_admin(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if config.pending_admin.as_ref() != Some(&info.sender) {
         return Err(ContractError::Unauthorized {  });
     }
+    config.admin = config.pending_admin.take();
+    CONFIG.save(deps.storage, &config)?;
+    Ok(Response::new().add_attributes(vec![
+        attr("action", "accept_admin"),
+        attr("admin", info.sender.to_string()),
+    ]))
+}
+
+pub fn renounce_admin(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    ensure_admin(&config, &info.sender)?;
+    config.admin = None;
+    config.pending_admin = None;
+    CONFIG.save(deps.storage, &config)?;
+    Ok(Response::new().add_attribute("action", "renounce_admin"))
+}
+
+pub fn grant_role(deps: DepsMut, info: MessageInfo, address: String, role: Role) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_admin(&config, &info.sender)?;
+    let addr = deps.api.addr_validate(&address)?;
+    ROLES.save(deps.storage, &addr, &role)?;
+    Ok(Response::new().add_attributes(vec![
+        attr("action", "grant_role"),
+        attr("address", addr.to_string()),
+        attr("role", format!("{:?}", role)),
+    ]))
+}
+
+pub fn revoke_role(deps: DepsMut, info: MessageInfo, address: String) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_admin(&config, &info.sender)?;
+    let addr = deps.api.addr_validate(&address)?;
+    ROLES.remove(deps.storage, &addr);
+    Ok(Response::new().add_attributes(vec![
+        attr("action", "revoke_role"),
+        attr("address", addr.to_string()),
+    ]))
+}
+
+/// Pauses or resumes each operation given, unset ones are left as they are
+pub fn set_paused(
+    deps: DepsMut,
+    info: MessageInfo,
+    deposit: Option<bool>,
+    borrow: Option<bool>,
+    withdraw: Option<bool>,
+    payoff: Option<bool>,
+    liquidate: Option<bool>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_role(deps.storage, &config, &info.sender, Role::PauseGuardian)?;
+    let mut paused = PAUSE_STATE.load(deps.storage)?;
+    paused.deposit = deposit.unwrap_or(paused.deposit);
+    paused.borrow = borrow.unwrap_or(paused.borrow);
+    paused.withdraw = withdraw.unwrap_or(paused.withdraw);
+    paused.payoff = payoff.unwrap_or(paused.payoff);
+    paused.liquidate = liquidate.unwrap_or(paused.liquidate);
+    PAUSE_STATE.save(deps.storage, &paused)?;
+
+    Ok(Response::new().add_attributes(vec![
+        attr("action", "set_paused"),
+        attr("deposit", paused.deposit.to_string()),
+        attr("borrow", paused.borrow.to_string()),
+        attr("withdraw", paused.withdraw.to_string()),
+        attr("payoff", paused.payoff.to_string()),
+        attr("liquidate", paused.liquidate.to_string()),
+    ]))
+}
+
+/// Response of an admin action, with its changes repeated in a
+/// `lending/config_update` event
+fn config_update_response(action: &str, attributes: Vec<Attribute>) -> Response {
+    Response::new()
+        .add_attribute("action", action)
+        .add_attributes(attributes.clone())
+        .add_event(events::config_update(action, attributes))
+}
+
+pub fn set_lending_token_addr(deps: DepsMut, info: MessageInfo, address: String) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage).unwrap();
+    ensure_admin(&config, &info.sender)?;
     let contract_addr = deps.api.addr_validate(&address)?;
     CONFIG.save(deps.storage, 
         &Config{ 
-            admin: config.admin, 
-            generic_token: config.generic_token, 
-            lending_token: Some(contract_addr)
+            lending_token: Some(contract_addr.clone()),
+            ..config
         }
     )?;
-    Ok(Response::default())
+    Ok(config_update_response("set_lending_token", vec![attr("lending_token", contract_addr)]))
 }
 
-pub fn get_total_owed(storage: &mut dyn Storage, env: Env, addr: Addr) -> Uint128 {
-    let loans: StdResult<Vec<_>> = LOANS.prefix(&addr).range(storage, None, None, Order::Ascending).collect();
-    let mut total_loan = Decimal::zero();
-    for (_, loan_info) in loans.unwrap() {
-        let updated_loan_info = loan_info.update_loan(env.block.time);
-        total_loan += updated_loan_info.amount_owed;
+pub fn set_receipt_token_addr(deps: DepsMut, info: MessageInfo, address: String) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_admin(&config, &info.sender)?;
+    let contract_addr = deps.api.addr_validate(&address)?;
+    CONFIG.save(deps.storage, 
+        &Config{ 
+            receipt_token: Some(contract_addr.clone()),
+            ..config
+        }
+    )?;
+    Ok(config_update_response("set_receipt_token", vec![attr("receipt_token", contract_addr)]))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    oracle: Option<String>,
+    max_price_age: Option<u64>,
+    close_factor: Option<Decimal>,
+    reserve_factor: Option<Decimal>,
+    treasury: Option<String>,
+    term_loan_rate: Option<Decimal>,
+    penalty_rate: Option<Decimal>,
+    grace_period: Option<u64>,
+    flash_loan_fee: Option<Decimal>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    ensure_admin(&config, &info.sender)?;
+    if let Some(oracle) = oracle {
+        config.oracle = deps.api.addr_validate(&oracle)?;
+    }
+    if let Some(max_price_age) = max_price_age {
+        config.max_price_age = max_price_age;
+    }
+    if let Some(close_factor) = close_factor {
+        config.close_factor = close_factor;
+    }
+    if let Some(reserve_factor) = reserve_factor {
+        config.reserve_factor = reserve_factor;
+    }
+    if let Some(treasury) = treasury {
+        config.treasury = deps.api.addr_validate(&treasury)?;
+    }
+    if let Some(term_loan_rate) = term_loan_rate {
+        config.term_loan_rate = term_loan_rate;
+    }
+    if let Some(penalty_rate) = penalty_rate {
+        config.penalty_rate = penalty_rate;
     }
-    total_loan.atomics()
+    if let Some(grace_period) = grace_period {
+        config.grace_period = grace_period;
+    }
+    if let Some(flash_loan_fee) = flash_loan_fee {
+        config.flash_loan_fee = flash_loan_fee;
+    }
+    validate_config(&config)?;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(config_update_response("update_config", vec![
+        attr("oracle", config.oracle.to_string()),
+        attr("max_price_age", config.max_price_age.to_string()),
+        attr("close_factor", config.close_factor.to_string()),
+        attr("reserve_factor", config.reserve_factor.to_string()),
+        attr("treasury", config.treasury.to_string()),
+        attr("term_loan_rate", config.term_loan_rate.to_string()),
+        attr("penalty_rate", config.penalty_rate.to_string()),
+        attr("grace_period", config.grace_period.to_string()),
+        attr("flash_loan_fee", config.flash_loan_fee.to_string()),
+    ]))
+}
+
+pub fn list_market(
+    deps: DepsMut,
+    info: MessageInfo,
+    token: String,
+    market: MarketConfig,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_role(deps.storage, &config, &info.sender, Role::RiskManager)?;
+    validate_market(&market)?;
+    match MARKETS.may_load(deps.storage, &token)? {
+        // deposits are paid out by the kind of token they were taken in as
+        Some(listed) if listed.native != market.native => {
+            return Err(ContractError::InvalidConfig { reason: "native cannot change for a listed market".to_string() });
+        },
+        Some(_) => {},
+        None => if !market.native {
+            deps.api.addr_validate(&token)?;
+        },
+    }
+    MARKETS.save(deps.storage, &token, &market)?;
+    if !COLLATERAL_STATE.has(deps.storage, &token) {
+        COLLATERAL_STATE.save(deps.storage, &token, &CollateralState::default())?;
+    }
+
+    Ok(config_update_response("list_market", vec![
+        attr("token", token),
+        attr("native", market.native.to_string()),
+        attr("collateral_factor", market.collateral_factor.to_string()),
+        attr("liquidation_threshold", market.liquidation_threshold.to_string()),
+        attr("liquidation_bonus", market.liquidation_bonus.to_string()),
+        attr("supply_cap", market.supply_cap.map_or("none".to_string(), |cap| cap.to_string())),
+    ]))
+}
+
+pub fn set_borrow_cap(deps: DepsMut, info: MessageInfo, borrow_cap: Option<Uint128>) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    ensure_role(deps.storage, &config, &info.sender, Role::RiskManager)?;
+    config.borrow_cap = borrow_cap;
+    CONFIG.save(deps.storage, &config)?;
+    Ok(config_update_response("set_borrow_cap", vec![
+        attr("borrow_cap", borrow_cap.map_or("none".to_string(), |cap| cap.to_string())),
+    ]))
+}
+
+pub fn update_interest_rate_model(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    model: InterestRateModelConfig,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_role(deps.storage, &config, &info.sender, Role::RiskManager)?;
+    model.validate()?;
+    // interest up to now accrues at the previous model's rate
+    accrue_interest(deps.storage, &env)?;
+    RATE_MODEL.save(deps.storage, &model)?;
+    let model = String::from_utf8(to_vec(&model)?).map_err(StdError::from)?;
+    Ok(config_update_response("update_interest_rate_model", vec![attr("model", model)]))
+}
+
+pub fn withdraw_reserves(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+    recipient: Option<String>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_role(deps.storage, &config, &info.sender, Role::Treasurer)?;
+    let lending_token = lending_token_addr(&config)?;
+    let recipient = match recipient {
+        Some(recipient) => deps.api.addr_validate(&recipient)?,
+        None => config.treasury,
+    };
+    let mut state = accrue_interest(deps.storage, &env)?;
+    if amount > state.total_reserves {
+        return Err(ContractError::InsufficientFunds {  });
+    }
+    state.total_reserves -= amount;
+    let minted = state.pay_out(amount);
+    MARKET_STATE.save(deps.storage, &state)?;
+
+    let messages = pay_lending_tokens(&lending_token, &recipient, amount, minted)?;
+    Ok(Response::new().add_messages(messages).add_attributes(vec![
+        attr("action", "withdraw_reserves"),
+        attr("recipient", recipient.to_string()),
+        attr("amount", amount.to_string()),
+    ]))
+}
+
+fn ensure_not_paused(paused: bool, action: &str) -> Result<(), ContractError> {
+    if paused {
+        return Err(ContractError::Paused { action: action.to_string() });
+    }
+    Ok(())
+}
+
+fn ensure_admin(config: &Config, sender: &Addr) -> Result<(), ContractError> {
+    if config.admin.as_ref() != Some(sender) {
+        return Err(ContractError::Unauthorized {  });
+    }
+    Ok(())
+}
+
+/// Errors unless `sender` is the admin or was granted `role`
+fn ensure_role(storage: &dyn Storage, config: &Config, sender: &Addr, role: Role) -> Result<(), ContractError> {
+    if config.admin.as_ref() == Some(sender) || ROLES.may_load(storage, sender)? == Some(role) {
+        return Ok(());
+    }
+    Err(ContractError::Unauthorized {  })
+}
+
+pub(crate) fn validate_config(config: &Config) -> Result<(), ContractError> {
+    if config.close_factor.is_zero() || config.close_factor > Decimal::one() {
+        return Err(ContractError::InvalidConfig { reason: "close_factor must be in (0, 1]".to_string() });
+    }
+    if config.reserve_factor > Decimal::one() {
+        return Err(ContractError::InvalidConfig { reason: "reserve_factor must not exceed 1".to_string() });
+    }
+    if config.flash_loan_fee > Decimal::one() {
+        return Err(ContractError::InvalidConfig { reason: "flash_loan_fee must not exceed 1".to_string() });
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_market(market: &MarketConfig) -> Result<(), ContractError> {
+    if market.collateral_factor > market.liquidation_threshold {
+        return Err(ContractError::InvalidConfig {
+            reason: "collateral_factor must not exceed liquidation_threshold".to_string(),
+        });
+    }
+    if market.liquidation_threshold.is_zero() || market.liquidation_threshold > Decimal::one() {
+        return Err(ContractError::InvalidConfig { reason: "liquidation_threshold must be in (0, 1]".to_string() });
+    }
+    if market.liquidation_bonus >= Decimal::one() {
+        return Err(ContractError::InvalidConfig { reason: "liquidation_bonus must be below 1".to_string() });
+    }
+    Ok(())
+}
+
+/// Sends `amount` of collateral `token` to `recipient`, through the bank
+/// module for native markets
+fn transfer_collateral(token: &str, market: &MarketConfig, recipient: &Addr, amount: Uint128) -> StdResult<CosmosMsg> {
+    if market.native {
+        return Ok(BankMsg::Send { to_address: recipient.to_string(), amount: coins(amount.u128(), token) }.into());
+    }
+    Cw20Contract(Addr::unchecked(token)).call(
+        Cw20ExecuteMsg::Transfer { recipient: recipient.to_string(), amount }
+    )
+}
+
+/// Balance of collateral `token` held by the contract
+fn collateral_balance(querier: &QuerierWrapper, env: &Env, token: &str, market: &MarketConfig) -> StdResult<Uint128> {
+    if market.native {
+        return Ok(querier.query_balance(&env.contract.address, token)?.amount);
+    }
+    let res: BalanceResponse = querier.query_wasm_smart(
+        token,
+        &Cw20QueryMsg::Balance { address: env.contract.address.to_string() },
+    )?;
+    Ok(res.balance)
+}
+
+/// Fresh oracle price of a collateral token in lending tokens
+fn get_collateral_price(querier: &QuerierWrapper, env: &Env, config: &Config, token: &str) -> Result<Decimal, ContractError> {
+    let res = query_price(querier, env, &config.oracle, token, config.max_price_age)?;
+    Ok(res.price)
+}
+
+/// A user's deposits valued at fresh oracle prices
+struct AccountValue {
+    /// deposits valued in lending tokens
+    collateral_value: Uint128,
+    /// max debt the deposits can back when borrowing or withdrawing
+    borrow_limit: Uint128,
+    /// debt above which the deposits can be liquidated
+    liquidation_limit: Uint128,
+    /// price used for each deposited token
+    prices: Vec<(String, Decimal)>,
+}
+
+impl AccountValue {
+    fn price(&self, token: &str) -> Decimal {
+        self.prices.iter()
+            .find(|(price_token, _)| price_token == token)
+            .map(|(_, price)| *price)
+            .unwrap_or_default()
+    }
+}
+
+/// `deposits` held as deposit receipts, in the tokens they are worth
+fn deposit_amounts(storage: &dyn Storage, deposits: &[CollateralDeposit]) -> StdResult<Vec<CollateralDeposit>> {
+    deposits.iter()
+        .map(|deposit| {
+            let collateral = COLLATERAL_STATE.load(storage, &deposit.token)?;
+            Ok(CollateralDeposit { token: deposit.token.clone(), amount: collateral.underlying_for(deposit.amount) })
+        })
+        .collect()
+}
+
+/// Amount of `token` the user's deposit receipts are worth
+fn underlying_deposit(storage: &dyn Storage, user_data: &UserData, token: &str) -> StdResult<Uint128> {
+    let collateral = COLLATERAL_STATE.may_load(storage, token)?.unwrap_or_default();
+    Ok(collateral.underlying_for(user_data.deposited(token)))
+}
+
+/// Values `deposits` across their markets, each weighted by its own
+/// collateral factor and liquidation threshold
+fn account_value(
+    storage: &dyn Storage,
+    querier: &QuerierWrapper,
+    env: &Env,
+    config: &Config,
+    deposits: &[CollateralDeposit],
+) -> Result<AccountValue, ContractError> {
+    let mut account = AccountValue {
+        collateral_value: Uint128::zero(),
+        borrow_limit: Uint128::zero(),
+        liquidation_limit: Uint128::zero(),
+        prices: vec![],
+    };
+    for deposit in deposits {
+        let market = MARKETS.load(storage, &deposit.token)?;
+        let price = get_collateral_price(querier, env, config, &deposit.token)?;
+        let value = to_value(deposit.amount, price);
+        account.collateral_value += value;
+        account.borrow_limit += value * market.collateral_factor;
+        account.liquidation_limit += value * market.liquidation_threshold;
+        account.prices.push((deposit.token.clone(), price));
+    }
+    Ok(account)
+}
+
+/// Records oracle prices so deposits can be valued for utilization
+fn cache_prices(storage: &mut dyn Storage, prices: &[(String, Decimal)]) -> StdResult<()> {
+    for (token, price) in prices {
+        let mut collateral = COLLATERAL_STATE.load(storage, token)?;
+        collateral.last_price = *price;
+        COLLATERAL_STATE.save(storage, token, &collateral)?;
+    }
+    Ok(())
+}
+
+/// Deposits of every market valued at the last seen oracle prices
+fn total_collateral_value(storage: &dyn Storage) -> StdResult<Uint128> {
+    COLLATERAL_STATE
+        .range(storage, None, None, Order::Ascending)
+        .try_fold(Uint128::zero(), |total, item| {
+            let (_, collateral) = item?;
+            Ok(total + to_value(collateral.total_deposits, collateral.last_price))
+        })
+}
+
+/// Market state with the borrow index and the supplier and reserve totals
+/// brought up to the current block time. Interest since the last accrual is
+/// charged at the rate the model gave for the utilization at that time.
+pub(crate) fn accrued_market_state(storage: &dyn Storage, env: &Env) -> StdResult<MarketState> {
+    let model = RATE_MODEL.load(storage)?;
+    let reserve_factor = CONFIG.load(storage)?.reserve_factor;
+    let mut state = MARKET_STATE.load(storage)?;
+    let rate = model.borrow_rate(state.utilization(total_collateral_value(storage)?));
+    state.accrue(env.block.time, rate, reserve_factor);
+    Ok(state)
+}
+
+/// Accrues interest into the borrow index and persists it. Must run before
+/// any change to deposits or debt so past interest uses the old utilization.
+fn accrue_interest(storage: &mut dyn Storage, env: &Env) -> StdResult<MarketState> {
+    let state = accrued_market_state(storage, env)?;
+    MARKET_STATE.save(storage, &state)?;
+    Ok(state)
+}
+
+/// Everything `addr` owes: the debt tracked through the borrow index plus
+/// each term loan accrued up to the time `state` was accrued to
+fn user_debt(storage: &dyn Storage, state: &MarketState, addr: &Addr, user_data: &UserData) -> StdResult<Uint128> {
+    let mut amount_owed = state.unscale(user_data.scaled_debt);
+    for loan_id in TERM_LOANS.prefix(addr).keys(storage, None, None, Order::Ascending) {
+        let loan_info = LOANS.load(storage, (addr, loan_id?))?;
+        amount_owed += loan_info.update_loan(state.last_accrual_time).amount_owed(state);
+    }
+    Ok(amount_owed)
+}
+
+pub fn get_total_owed(storage: &dyn Storage, env: &Env, addr: &Addr) -> StdResult<Uint128> {
+    let state = accrued_market_state(storage, env)?;
+    let user_data = USER_INFO.may_load(storage, addr)?.unwrap_or_default();
+    user_debt(storage, &state, addr, &user_data)
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::GetUserInfo {address} => to_binary(&get_user_info(deps, env,address)?),
+        QueryMsg::CollateralPrice { token } => to_binary(&get_collateral_price_info(deps, env, token)?),
+        QueryMsg::Markets {} => to_binary(&get_markets(deps)?),
+        QueryMsg::InterestRate {} => to_binary(&get_interest_rate(deps, env)?),
+        QueryMsg::SupplyInfo {} => to_binary(&get_supply_info(deps, env)?),
+        QueryMsg::Reserves {} => to_binary(&get_reserves(deps, env)?),
+        QueryMsg::Admin {} => to_binary(&get_admin(deps)?),
+        QueryMsg::Roles {} => to_binary(&get_roles(deps)?),
+        QueryMsg::AccountLiquidity { address } => to_binary(&get_account_liquidity(deps, env, address)?),
+        QueryMsg::AllUsers { start_after, limit } => to_binary(&query_all_users(deps, start_after, limit)?),
+        QueryMsg::Loans { address, start_after, limit } => to_binary(&query_loans(deps, env, address, start_after, limit)?),
+        QueryMsg::BorrowDelegations { owner, start_after, limit } => {
+            to_binary(&query_borrow_delegations(deps, owner, start_after, limit)?)
+        },
+        QueryMsg::Config {} => to_binary(&get_config(deps)?),
+        QueryMsg::MarketState {} => to_binary(&get_market_state(deps, env)?),
+        QueryMsg::ProjectedDebt { address, at_time } => to_binary(&get_projected_debt(deps, env, address, at_time)?),
+        QueryMsg::SimulateBorrow { address, amount, term } => {
+            to_binary(&simulate_borrow(deps, env, address, amount, term)?)
+        },
+        QueryMsg::SimulateWithdraw { address, token, amount } => {
+            to_binary(&simulate_withdraw(deps, env, address, token, amount)?)
+        },
+        QueryMsg::SimulatePayoff { address, amount, loan_id, policy } => {
+            to_binary(&simulate_payoff(deps, env, address, amount, loan_id, policy)?)
+        },
+        QueryMsg::Capacity {} => to_binary(&get_capacity(deps, env)?),
+        QueryMsg::PauseStatus {} => to_binary(&PAUSE_STATE.load(deps.storage)?),
     }
 }
 
-pub fn get_user_info(deps: Deps, env: Env, address: String) -> StdResult<Option<UserInfoResponse>> {
+pub fn get_admin(deps: Deps) -> StdResult<AdminResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(AdminResponse { admin: config.admin, pending_admin: config.pending_admin })
+}
+
+pub fn get_roles(deps: Deps) -> StdResult<RolesResponse> {
+    let roles: StdResult<Vec<_>> = ROLES
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (address, role) = item?;
+            Ok(RoleInfo { address, role })
+        })
+        .collect();
+    Ok(RolesResponse { roles: roles? })
+}
+
+pub fn get_reserves(deps: Deps, env: Env) -> StdResult<ReservesResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let state = accrued_market_state(deps.storage, &env)?;
+    Ok(ReservesResponse {
+        treasury: config.treasury,
+        reserve_factor: config.reserve_factor,
+        total_reserves: state.total_reserves,
+    })
+}
+
+pub fn get_supply_info(deps: Deps, env: Env) -> StdResult<SupplyInfoResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let state = accrued_market_state(deps.storage, &env)?;
+    Ok(SupplyInfoResponse {
+        receipt_token: config.receipt_token,
+        total_supply: state.total_supply,
+        total_receipts: state.total_receipts,
+        exchange_rate: state.exchange_rate(),
+    })
+}
+
+pub fn get_config(deps: Deps) -> StdResult<ConfigResponse> {
+    Ok(ConfigResponse {
+        config: CONFIG.load(deps.storage)?,
+        contract_version: get_contract_version(deps.storage)?,
+    })
+}
+
+pub fn get_market_state(deps: Deps, env: Env) -> StdResult<MarketStateResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let model = RATE_MODEL.load(deps.storage)?;
+    let state = accrued_market_state(deps.storage, &env)?;
+    let total_deposits: StdResult<Vec<_>> = COLLATERAL_STATE
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (token, collateral) = item?;
+            Ok(CollateralDeposit { token, amount: collateral.total_deposits })
+        })
+        .collect();
+    let total_collateral_value = total_collateral_value(deps.storage)?;
+    let utilization = state.utilization(total_collateral_value);
+    let borrow_rate = model.borrow_rate(utilization);
+    // suppliers earn on the variable debt the pool funded, once receipts are out
+    let total_borrows = state.total_borrows();
+    let supply_rate = if state.total_receipts.is_zero() || state.total_supply.is_zero() || total_borrows.is_zero() {
+        Decimal::zero()
+    } else {
+        let variable_debt = state.unscale(state.total_scaled_debt);
+        let pool_funded = variable_debt.multiply_ratio(std::cmp::min(state.total_lent, total_borrows), total_borrows);
+        Decimal::from_ratio(pool_funded, state.total_supply)
+            * borrow_rate
+            * (Decimal::one() - config.reserve_factor)
+    };
+    Ok(MarketStateResponse {
+        total_deposits: total_deposits?,
+        total_collateral_value,
+        total_borrows: state.total_borrows(),
+        total_supply: state.total_supply,
+        total_reserves: state.total_reserves,
+        utilization,
+        borrow_rate,
+        supply_rate,
+    })
+}
+
+pub fn get_projected_debt(deps: Deps, env: Env, address: String, at_time: u64) -> StdResult<ProjectedDebtResponse> {
+    if at_time < env.block.time.seconds() {
+        return Err(StdError::generic_err("at_time must not be in the past"));
+    }
     let address = deps.api.addr_validate(&address)?;
-    // accumulate loans and add totals
-    let loans: StdResult<Vec<_>> = LOANS.prefix(&address).range(deps.storage, None, None, Order::Ascending).collect();
-    let mut total_loan = Decimal::zero();
-    for (_, loan_info) in loans.unwrap() {
-        let updated_loan_info = loan_info.update_loan(env.block.time);
-        total_loan += updated_loan_info.amount_owed;
+    let ts = Timestamp::from_seconds(at_time);
+    let model = RATE_MODEL.load(deps.storage)?;
+    let borrow_rate = model.borrow_rate(MARKET_STATE.load(deps.storage)?.utilization(total_collateral_value(deps.storage)?));
+    // accruing straight to `at_time` is what the next interaction would do
+    let mut projected_env = env;
+    projected_env.block.time = ts;
+    let projected_state = accrued_market_state(deps.storage, &projected_env)?;
+
+    let mut loans = vec![];
+    let mut total_owed = Uint128::zero();
+    for item in LOANS.prefix(&address).range(deps.storage, None, None, Order::Ascending) {
+        let (loan_id, loan_info) = item?;
+        let rate = match &loan_info.term {
+            Some(term) if at_time > term.maturity.seconds() + term.grace_period => term.rate + term.penalty_rate,
+            Some(term) => term.rate,
+            None => borrow_rate,
+        };
+        let amount_owed = loan_info.update_loan(ts).amount_owed(&projected_state);
+        total_owed += amount_owed;
+        loans.push(ProjectedLoan { loan_id: loan_id.into(), amount_owed, rate });
     }
+    let apr = if total_owed.is_zero() {
+        Decimal::zero()
+    } else {
+        loans.iter().fold(Decimal::zero(), |apr, loan| apr + Decimal::from_ratio(loan.amount_owed, total_owed) * loan.rate)
+    };
+    let daily = Decimal::one() + apr * Decimal::from_ratio(1_u128, 365_u128);
+    let apy = (0..365).fold(Decimal::one(), |acc, _| acc * daily) - Decimal::one();
+
+    Ok(ProjectedDebtResponse { at_time, loans, total_owed, apr, apy })
+}
+
+pub fn get_interest_rate(deps: Deps, env: Env) -> StdResult<InterestRateResponse> {
+    let model = RATE_MODEL.load(deps.storage)?;
+    let collateral_value = total_collateral_value(deps.storage)?;
+    let utilization = accrued_market_state(deps.storage, &env)?.utilization(collateral_value);
+    Ok(InterestRateResponse {
+        borrow_rate: model.borrow_rate(utilization),
+        utilization,
+        model,
+    })
+}
+
+pub fn get_collateral_price_info(deps: Deps, env: Env, token: String) -> StdResult<PriceResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    query_price(&deps.querier, &env, &config.oracle, &token, config.max_price_age)
+        .map_err(|err| StdError::generic_err(err.to_string()))
+}
+
+pub fn get_capacity(deps: Deps, env: Env) -> StdResult<CapacityResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let state = accrued_market_state(deps.storage, &env)?;
+    let total_borrows = state.total_borrows();
+    let markets: StdResult<Vec<_>> = MARKETS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (token, market) = item?;
+            let collateral = COLLATERAL_STATE.load(deps.storage, &token)?;
+            Ok(MarketCapacity {
+                token,
+                supply_cap: market.supply_cap,
+                total_deposits: collateral.total_deposits,
+                remaining: market.supply_cap.map(|cap| cap.saturating_sub(collateral.total_deposits)),
+            })
+        })
+        .collect();
+    Ok(CapacityResponse {
+        borrow_cap: config.borrow_cap,
+        total_borrows,
+        remaining_borrows: config.borrow_cap.map(|cap| cap.saturating_sub(total_borrows)),
+        markets: markets?,
+    })
+}
+
+pub fn get_markets(deps: Deps) -> StdResult<MarketsResponse> {
+    let markets: StdResult<Vec<_>> = MARKETS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (token, config) = item?;
+            let collateral = COLLATERAL_STATE.load(deps.storage, &token)?;
+            Ok(MarketInfo {
+                token,
+                config,
+                total_deposits: collateral.total_deposits,
+                last_price: collateral.last_price,
+            })
+        })
+        .collect();
+    Ok(MarketsResponse { markets: markets? })
+}
+
+pub fn get_account_liquidity(deps: Deps, env: Env, address: String) -> StdResult<AccountLiquidityResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let config = CONFIG.load(deps.storage)?;
+    let state = accrued_market_state(deps.storage, &env)?;
+    let user_data = USER_INFO.may_load(deps.storage, &address)?.unwrap_or_default();
+    let debt = user_debt(deps.storage, &state, &address, &user_data)?;
+    let deposits = deposit_amounts(deps.storage, &user_data.collateral())?;
+    let account = account_value(deps.storage, &deps.querier, &env, &config, &deposits)
+        .map_err(|err| StdError::generic_err(err.to_string()))?;
+
+    let mut max_borrow = account.borrow_limit.saturating_sub(debt);
+    if let Some(cap) = config.borrow_cap {
+        max_borrow = std::cmp::min(max_borrow, cap.saturating_sub(state.total_borrows()));
+    }
+    let mut max_withdraw = vec![];
+    for deposit in &deposit_amounts(deps.storage, &user_data.deposits)? {
+        let amount = if user_data.is_collateral(&deposit.token) {
+            let market = MARKETS.load(deps.storage, &deposit.token)?;
+            max_withdraw_amount(&account, &market, deposit, debt)
+        } else {
+            deposit.amount
+        };
+        max_withdraw.push(CollateralDeposit { token: deposit.token.clone(), amount });
+    }
+
+    Ok(AccountLiquidityResponse {
+        collateral_value: account.collateral_value,
+        debt_value: debt,
+        health_factor: if debt.is_zero() { None } else { Some(Decimal::from_ratio(account.liquidation_limit, debt)) },
+        max_borrow,
+        max_withdraw,
+    })
+}
+
+/// Largest amount of `deposit` that `try_withdraw` lets go of while `debt`
+/// stays within the borrow limit
+fn max_withdraw_amount(account: &AccountValue, market: &MarketConfig, deposit: &CollateralDeposit, debt: Uint128) -> Uint128 {
+    if debt.is_zero() || market.collateral_factor.is_zero() {
+        return deposit.amount;
+    }
+    let price = account.price(&deposit.token);
+    let deposit_limit = to_value(deposit.amount, price) * market.collateral_factor;
+    let other_limit = account.borrow_limit - deposit_limit;
+    if other_limit >= debt {
+        return deposit.amount;
+    }
+    // smallest value and then amount left in the deposit that still covers
+    // the debt, valuing rounds down so each is rounded up by at most one
+    let needed = debt - other_limit;
+    let mut value = needed.multiply_ratio(Decimal::one().atomics(), market.collateral_factor.atomics());
+    if value * market.collateral_factor < needed {
+        value += Uint128::new(1);
+    }
+    let mut kept = from_value(value, price);
+    if to_value(kept, price) < value {
+        kept += Uint128::new(1);
+    }
+    deposit.amount.saturating_sub(kept)
+}
+
+pub fn get_user_info(deps: Deps, env: Env, address: String) -> StdResult<Option<UserInfoResponse>> {
+    let address = deps.api.addr_validate(&address)?;
+    let total_loan = get_total_owed(deps.storage, &env, &address)?;
     let res = match USER_INFO.may_load(deps.storage, &address) {
-        Ok(Some(user_info)) => Some(
-            UserInfoResponse { 
-                generic_token_deposited: user_info.generic_token_deposited,
-                lending_token_withdrawed: user_info.borrow_amt,
-                total_loan_owed: total_loan.atomics(), 
+        Ok(Some(user_info)) => {
+            let config = CONFIG.load(deps.storage)?;
+            // a position must stay readable while the oracle is down, so
+            // unusable prices fall back to the last ones the contract saw
+            let mut collateral_value = Uint128::zero();
+            let mut prices = vec![];
+            for deposit in deposit_amounts(deps.storage, &user_info.collateral())? {
+                let (price, stale) = match get_collateral_price(&deps.querier, &env, &config, &deposit.token) {
+                    Ok(price) => (price, false),
+                    Err(_) => (COLLATERAL_STATE.load(deps.storage, &deposit.token)?.last_price, true),
+                };
+                collateral_value += to_value(deposit.amount, price);
+                prices.push(DepositPrice { token: deposit.token, price, stale });
             }
-        ),
+            let price_stale = prices.iter().any(|price| price.stale);
+            Some(
+                UserInfoResponse { 
+                    deposits: deposit_amounts(deps.storage, &user_info.deposits)?,
+                    deposit_receipts: user_info.deposits,
+                    lending_token_withdrawed: user_info.borrow_amt,
+                    total_loan_owed: total_loan, 
+                    collateral_value,
+                    price_stale,
+                    prices,
+                    supply_only: user_info.supply_only,
+                }
+            )
+        },
         Ok(None) => None,
         Err(_) => None,
     };
@@ -265,8 +1819,39 @@ pub fn get_user_info(deps: Deps, env: Env, address: String) -> StdResult<Option<
 #[cfg(test)]
 mod tests {
     use super::*;
-    use cosmwasm_std::testing::{mock_env, mock_info, mock_dependencies};   
-    use cosmwasm_std::{to_binary, Uint128};
+    use crate::interest_rate::FixedRateModel;
+    use crate::state::SECONDS_PER_YEAR;
+    use cosmwasm_std::testing::{mock_env, mock_info, mock_dependencies, MockApi, MockQuerier, MockStorage};   
+    use cosmwasm_std::{to_binary, ContractResult, OwnedDeps, SystemResult, Timestamp, Uint128};
+
+    /// Instantiate message for a generic collateral market valued at par,
+    /// tests override the fields they exercise
+    fn default_instantiate_msg() -> InstantiateMsg {
+        InstantiateMsg {
+            admin: "admin".to_string(),
+            generic_token: "generic".to_string(),
+            oracle: "oracle".to_string(),
+            max_price_age: 3600,
+            collateral_factor: Decimal::one(),
+            liquidation_threshold: Decimal::one(),
+            liquidation_bonus: Decimal::percent(5),
+            close_factor: Decimal::percent(50),
+            reserve_factor: Decimal::zero(),
+            treasury: "treasury".to_string(),
+            term_loan_rate: Decimal::percent(5),
+            penalty_rate: Decimal::percent(20),
+            grace_period: 86400 * 7,
+            flash_loan_fee: Decimal::zero(),
+            interest_rate_model: InterestRateModelConfig::Fixed(FixedRateModel { rate: Decimal::percent(3) }),
+        }
+    }
+
+    fn mock_oracle_price(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, price: Decimal, last_updated: Timestamp) {
+        deps.querier.update_wasm(move |_| {
+            let res = PriceResponse { price, last_updated: last_updated.seconds() };
+            SystemResult::Ok(ContractResult::Ok(to_binary(&res).unwrap()))
+        });
+    }
 
     #[test]
     fn basic_test() {
@@ -274,9 +1859,23 @@ mod tests {
         let instantiate_msg = InstantiateMsg{ 
             admin: "admin".to_string(), 
             generic_token: "token".to_string(), 
+            oracle: "oracle".to_string(),
+            max_price_age: 3600,
+            collateral_factor: Decimal::one(),
+            liquidation_threshold: Decimal::one(),
+            liquidation_bonus: Decimal::percent(5),
+            close_factor: Decimal::percent(50),
+            reserve_factor: Decimal::zero(),
+            treasury: "treasury".to_string(),
+            term_loan_rate: Decimal::percent(5),
+            penalty_rate: Decimal::percent(20),
+            grace_period: 86400 * 7,
+            flash_loan_fee: Decimal::zero(),
+            interest_rate_model: InterestRateModelConfig::Fixed(FixedRateModel { rate: Decimal::percent(3) }),
         };
         let info = mock_info("creator", &[]);
         let env = mock_env();
+        mock_oracle_price(&mut deps, Decimal::one(), env.block.time);
         let res = instantiate(deps.as_mut(), env.clone(), info, instantiate_msg).unwrap();
         assert_eq!(0, res.messages.len());
 
@@ -303,7 +1902,7 @@ mod tests {
         let response : StdResult<Option<UserInfoResponse>> = get_user_info(deps.as_ref(), env.clone(), "user1".to_string());
         assert_eq!(
             response,
-            Ok(Some(UserInfoResponse {generic_token_deposited: Uint128::from(100u128), lending_token_withdrawed: Uint128::zero(), total_loan_owed: Uint128::zero() }))
+            Ok(Some(UserInfoResponse {deposits: vec![CollateralDeposit { token: "token".to_string(), amount: Uint128::from(100u128) }], deposit_receipts: vec![CollateralDeposit { token: "token".to_string(), amount: Uint128::from(100u128) }], lending_token_withdrawed: Uint128::zero(), total_loan_owed: Uint128::zero(), collateral_value: Uint128::from(100u128), price_stale: false, prices: vec![DepositPrice { token: "token".to_string(), price: Decimal::one(), stale: false }], supply_only: vec![] }))
         );
 
         // test non-existent user
@@ -314,7 +1913,7 @@ mod tests {
         );
 
         // withdrawal test
-        let withdraw_msg = ExecuteMsg::Withdraw { amount: Uint128::from(99u128) };
+        let withdraw_msg = ExecuteMsg::Withdraw { token: "token".to_string(), amount: Uint128::from(99u128) };
         let info = mock_info("user1", &[]);
         let res = execute(deps.as_mut(), env.clone(), info.clone(), withdraw_msg.clone());
         match res {
@@ -325,20 +1924,19 @@ mod tests {
         let response : StdResult<Option<UserInfoResponse>> = get_user_info(deps.as_ref(), env.clone(), "user1".to_string());
         assert_eq!(
             response,
-            Ok(Some(UserInfoResponse { generic_token_deposited: Uint128::from(1u128), lending_token_withdrawed: Uint128::zero(), total_loan_owed: Uint128::zero() }))
+            Ok(Some(UserInfoResponse { deposits: vec![CollateralDeposit { token: "token".to_string(), amount: Uint128::from(1u128) }], deposit_receipts: vec![CollateralDeposit { token: "token".to_string(), amount: Uint128::from(1u128) }], lending_token_withdrawed: Uint128::zero(), total_loan_owed: Uint128::zero(), collateral_value: Uint128::from(1u128), price_stale: false, prices: vec![DepositPrice { token: "token".to_string(), price: Decimal::one(), stale: false }], supply_only: vec![] }))
         );
 
         // borrow test (insufficient funds)
-        let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::from(2u128) };
+        let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::from(2u128), term: None, on_behalf_of: None };
         let info = mock_info("user1", &[]);
         let res = execute(deps.as_mut(), env.clone(), info.clone(), borrow_msg.clone());
-        match res {
-            Ok(_) => panic!("Should have received an error"),
-            _ => {},
+        if res.is_ok() {
+            panic!("Should have received an error");
         }
 
         // borrow test (sufficient funds)
-        let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::from(1u128) };
+        let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::from(1u128), term: None, on_behalf_of: None };
         let info = mock_info("user1", &[]);
         let res = execute(deps.as_mut(), env.clone(), info.clone(), borrow_msg.clone());
         match res {
@@ -350,7 +1948,7 @@ mod tests {
         let user_info = get_user_info(deps.as_ref(), env.clone(), "user1".to_string()).unwrap();
         match user_info {
             Some(ui) => {
-                assert_eq!(ui.generic_token_deposited, Uint128::from(1u128));
+                assert_eq!(ui.collateral_value, Uint128::from(1u128));
                 assert_eq!(ui.lending_token_withdrawed, Uint128::from(1u128));
                 assert_eq!(ui.total_loan_owed, Uint128::from(1u128));
             },
@@ -358,12 +1956,11 @@ mod tests {
         }
 
         // borrow test (insufficient funds)
-        let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::from(2u128) };
+        let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::from(2u128), term: None, on_behalf_of: None };
         let info = mock_info("user1", &[]);
         let res = execute(deps.as_mut(), env.clone(), info.clone(), borrow_msg.clone());
-        match res {
-            Ok(_) => panic!("Should have received an error"),
-            _ => {},
+        if res.is_ok() {
+            panic!("Should have received an error");
         }
 
         // deposit more $$
@@ -380,7 +1977,7 @@ mod tests {
         }
 
         // borrow test, see if total borrow amount is correct
-        let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::from(50u128) };
+        let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::from(50u128), term: None, on_behalf_of: None };
         let info = mock_info("user1", &[]);
         let res = execute(deps.as_mut(), env.clone(), info.clone(), borrow_msg.clone());
         match res {
@@ -391,11 +1988,213 @@ mod tests {
         let user_info = get_user_info(deps.as_ref(), env.clone(), "user1".to_string()).unwrap();
         match user_info {
             Some(ui) => {
-                assert_eq!(ui.generic_token_deposited, Uint128::from(101u128));
+                assert_eq!(ui.collateral_value, Uint128::from(101u128));
                 assert_eq!(ui.lending_token_withdrawed, Uint128::from(51u128));
                 assert_eq!(ui.total_loan_owed, Uint128::from(51u128));
             },
             None => panic!("Should not be none!"),
         }
     }
+
+    #[test]
+    fn max_withdraw_amount_test() {
+        let price = Decimal::percent(130);
+        let market = MarketConfig {
+            native: false,
+            collateral_factor: Decimal::percent(75),
+            liquidation_threshold: Decimal::percent(80),
+            liquidation_bonus: Decimal::percent(5),
+            supply_cap: None,
+        };
+        let deposit = CollateralDeposit { token: "generic".to_string(), amount: Uint128::from(1000u128) };
+        let limit = |amount: u128| to_value(Uint128::from(amount), price) * market.collateral_factor;
+        let account = AccountValue {
+            collateral_value: to_value(deposit.amount, price),
+            borrow_limit: limit(1000),
+            liquidation_limit: to_value(deposit.amount, price) * market.liquidation_threshold,
+            prices: vec![("generic".to_string(), price)],
+        };
+        let debt = Uint128::from(500u128);
+
+        // both roundings fall short by one and are stepped up, what is left
+        // covers the debt and one unit less would not
+        let amount = max_withdraw_amount(&account, &market, &deposit, debt);
+        assert_eq!(amount, Uint128::from(486u128));
+        assert!(limit(1000 - 486) >= debt);
+        assert!(limit(1000 - 487) < debt);
+        assert_eq!(max_withdraw_amount(&account, &market, &deposit, Uint128::zero()), deposit.amount);
+    }
+
+    #[test]
+    fn collateral_factor_test() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg {
+            collateral_factor: Decimal::percent(75),
+            liquidation_threshold: Decimal::percent(80),
+            ..default_instantiate_msg()
+        };
+        let env = mock_env();
+        mock_oracle_price(&mut deps, Decimal::one(), env.block.time);
+        instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), instantiate_msg).unwrap();
+        let lend_token_addr_msg = ExecuteMsg::SetLendingTokenAddress { address: "lending".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), lend_token_addr_msg).unwrap();
+
+        // collateral factor can not exceed liquidation threshold
+        let update_msg = ExecuteMsg::ListMarket {
+            token: "generic".to_string(),
+            native: false,
+            collateral_factor: Decimal::percent(90),
+            liquidation_threshold: Decimal::percent(80),
+            liquidation_bonus: Decimal::percent(5),
+            supply_cap: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), update_msg);
+        assert!(matches!(res, Err(ContractError::InvalidConfig { .. })));
+
+        let recv_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "user1".to_string(),
+            amount: Uint128::from(1000u128),
+            msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap(),
+        });
+        execute(deps.as_mut(), env.clone(), mock_info("generic", &[]), recv_msg).unwrap();
+
+        // only 75% of the deposit can be borrowed
+        let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::from(751u128), term: None, on_behalf_of: None };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("user1", &[]), borrow_msg);
+        assert!(matches!(res, Err(ContractError::InsufficientFunds {})));
+        let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::from(750u128), term: None, on_behalf_of: None };
+        execute(deps.as_mut(), env.clone(), mock_info("user1", &[]), borrow_msg).unwrap();
+
+        // withdrawing would take the user over their borrow limit
+        let withdraw_msg = ExecuteMsg::Withdraw { token: "generic".to_string(), amount: Uint128::from(1u128) };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("user1", &[]), withdraw_msg);
+        assert!(matches!(res, Err(ContractError::InsufficientFunds {})));
+
+        // after a year the debt (772) is above the collateral factor but below the threshold
+        let mut env = env;
+        env.block.time = env.block.time.plus_seconds(86400 * 365);
+        mock_oracle_price(&mut deps, Decimal::one(), env.block.time);
+        let liquidate_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "liquidator".to_string(),
+            amount: Uint128::from(100u128),
+            msg: to_binary(&Cw20HookMsg::Liquidate { borrower: "user1".to_string(), collateral: "generic".to_string() }).unwrap(),
+        });
+        let res = execute(deps.as_mut(), env.clone(), mock_info("lending", &[]), liquidate_msg.clone());
+        assert!(matches!(res, Err(ContractError::NotLiquidatable {})));
+
+        // only the admin may lower the threshold
+        let update_msg = ExecuteMsg::ListMarket {
+            token: "generic".to_string(),
+            native: false,
+            collateral_factor: Decimal::percent(75),
+            liquidation_threshold: Decimal::percent(75),
+            liquidation_bonus: Decimal::percent(5),
+            supply_cap: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("user1", &[]), update_msg.clone());
+        assert!(matches!(res, Err(ContractError::Unauthorized {})));
+        execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), update_msg).unwrap();
+
+        let res = execute(deps.as_mut(), env.clone(), mock_info("lending", &[]), liquidate_msg).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        let user_info = get_user_info(deps.as_ref(), env, "user1".to_string()).unwrap().unwrap();
+        assert_eq!(user_info.deposits[0].amount, Uint128::from(895u128));
+        assert_eq!(user_info.total_loan_owed, Uint128::from(672u128));
+    }
+
+    #[test]
+    fn rate_change_accrual_test() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = default_instantiate_msg();
+        let mut env = mock_env();
+        mock_oracle_price(&mut deps, Decimal::one(), env.block.time);
+        instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), instantiate_msg).unwrap();
+        let lend_token_addr_msg = ExecuteMsg::SetLendingTokenAddress { address: "lending".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), lend_token_addr_msg).unwrap();
+
+        let recv_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "user1".to_string(),
+            amount: Uint128::from(2000u128),
+            msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap(),
+        });
+        execute(deps.as_mut(), env.clone(), mock_info("generic", &[]), recv_msg).unwrap();
+        let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::from(1000u128), term: None, on_behalf_of: None };
+        execute(deps.as_mut(), env.clone(), mock_info("user1", &[]), borrow_msg).unwrap();
+
+        // half a year at 3%, then half a year at 10%
+        env.block.time = env.block.time.plus_seconds(SECONDS_PER_YEAR / 2);
+        let update_msg = ExecuteMsg::UpdateInterestRateModel {
+            model: InterestRateModelConfig::Fixed(FixedRateModel { rate: Decimal::percent(10) }),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), update_msg).unwrap();
+        env.block.time = env.block.time.plus_seconds(SECONDS_PER_YEAR / 2);
+
+        let owed = get_total_owed(deps.as_ref().storage, &env, &Addr::unchecked("user1")).unwrap();
+        assert_eq!(owed, Uint128::from(1065u128));
+
+        // borrowing again does not re-accrue the first loan
+        mock_oracle_price(&mut deps, Decimal::one(), env.block.time);
+        let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::from(500u128), term: None, on_behalf_of: None };
+        execute(deps.as_mut(), env.clone(), mock_info("user1", &[]), borrow_msg).unwrap();
+        // 1065.75 + 500, the fraction is no longer truncated away
+        let owed = get_total_owed(deps.as_ref().storage, &env, &Addr::unchecked("user1")).unwrap();
+        assert_eq!(owed, Uint128::from(1566u128));
+    }
+
+    #[test]
+    fn repayment_allocation_test() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = default_instantiate_msg();
+        let env = mock_env();
+        mock_oracle_price(&mut deps, Decimal::one(), env.block.time);
+        instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), instantiate_msg).unwrap();
+        let lend_token_addr_msg = ExecuteMsg::SetLendingTokenAddress { address: "lending".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), lend_token_addr_msg).unwrap();
+
+        let recv_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "user1".to_string(),
+            amount: Uint128::from(1000u128),
+            msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap(),
+        });
+        execute(deps.as_mut(), env.clone(), mock_info("generic", &[]), recv_msg).unwrap();
+        for amount in [100u128, 300, 200] {
+            let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::from(amount), term: None, on_behalf_of: None };
+            execute(deps.as_mut(), env.clone(), mock_info("user1", &[]), borrow_msg).unwrap();
+        }
+
+        type MockDeps = OwnedDeps<MockStorage, MockApi, MockQuerier>;
+        let payoff = |deps: &mut MockDeps, amount: u128, loan_id: Option<u128>, policy: Option<RepaymentPolicy>| {
+            let payoff_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: "user1".to_string(),
+                amount: Uint128::from(amount),
+                msg: to_binary(&Cw20HookMsg::Payoff { loan_id: loan_id.map(Uint128::from), policy }).unwrap(),
+            });
+            execute(deps.as_mut(), mock_env(), mock_info("lending", &[]), payoff_msg)
+        };
+        let allocations = |res: &Response| -> Vec<(String, String)> {
+            res.attributes.iter()
+                .filter(|attr| attr.key.starts_with("loan_"))
+                .map(|attr| (attr.key.clone(), attr.value.clone()))
+                .collect()
+        };
+
+        // the largest loan is repaid first
+        let res = payoff(&mut deps, 150, None, Some(RepaymentPolicy::HighestBalanceFirst)).unwrap();
+        assert_eq!(allocations(&res), vec![("loan_1".to_string(), "150".to_string())]);
+
+        // a targeted payment only closes that loan and refunds the rest
+        let res = payoff(&mut deps, 250, Some(2), None).unwrap();
+        assert_eq!(allocations(&res), vec![("loan_2".to_string(), "200".to_string())]);
+        assert_eq!(res.messages.len(), 2);
+        let res = payoff(&mut deps, 250, Some(2), None);
+        assert!(matches!(res, Err(ContractError::LoanNotFound { loan_id: 2 })));
+
+        let res = payoff(&mut deps, 200, None, Some(RepaymentPolicy::NewestFirst)).unwrap();
+        assert_eq!(allocations(&res), vec![
+            ("loan_1".to_string(), "150".to_string()),
+            ("loan_0".to_string(), "50".to_string()),
+        ]);
+        let owed = get_total_owed(deps.as_ref().storage, &env, &Addr::unchecked("user1")).unwrap();
+        assert_eq!(owed, Uint128::from(50u128));
+    }
 }
@@ -0,0 +1,82 @@
+use cosmwasm_std::{Deps, Env, Order, StdResult, Uint128};
+use cw_storage_plus::Bound;
+
+use crate::contract::accrued_market_state;
+use crate::msg::{AllUsersResponse, BorrowDelegationInfo, BorrowDelegationsResponse, LoanResponse, LoansResponse};
+use crate::state::{BORROW_DELEGATIONS, LOANS, USER_INFO};
+
+// settings for pagination
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
+
+pub fn query_all_users(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<AllUsersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|s| Bound::ExclusiveRaw(s.into_bytes()));
+
+    let users = USER_INFO
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(Into::into))
+        .collect::<StdResult<_>>()?;
+
+    Ok(AllUsersResponse { users })
+}
+
+pub fn query_loans(
+    deps: Deps,
+    env: Env,
+    address: String,
+    start_after: Option<Uint128>,
+    limit: Option<u32>,
+) -> StdResult<LoansResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|loan_id| Bound::exclusive(loan_id.u128()));
+    let state = accrued_market_state(deps.storage, &env)?;
+
+    let loans = LOANS
+        .prefix(&address)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            item.map(|(loan_id, loan)| {
+                let loan = loan.update_loan(state.last_accrual_time);
+                LoanResponse {
+                    loan_id: loan_id.into(),
+                    amount_owed: loan.amount_owed(&state),
+                    loan,
+                }
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(LoansResponse { loans })
+}
+
+pub fn query_borrow_delegations(
+    deps: Deps,
+    owner: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<BorrowDelegationsResponse> {
+    let owner_addr = deps.api.addr_validate(&owner)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|s| Bound::ExclusiveRaw(s.into_bytes()));
+
+    let delegations = BORROW_DELEGATIONS
+        .prefix(&owner_addr)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            item.map(|(addr, delegation)| BorrowDelegationInfo {
+                delegate: addr.into(),
+                limit: delegation.limit,
+                expires: delegation.expires,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(BorrowDelegationsResponse { delegations })
+}
@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -20,6 +20,60 @@ pub enum ContractError {
 
     #[error("Insufficient Funds")]
     InsufficientFunds {},
+
+    #[error("Position is not eligible for liquidation")]
+    NotLiquidatable {},
+
+    #[error("Oracle price is stale, last updated at {last_updated}")]
+    StalePrice { last_updated: u64 },
+
+    #[error("Oracle returned an invalid price")]
+    InvalidPrice {},
+
+    #[error("Invalid config: {reason}")]
+    InvalidConfig { reason: String },
+
+    #[error("No native collateral market for {denom}")]
+    UnknownDenom { denom: String },
+
+    #[error("Loan term must be greater than zero")]
+    InvalidTerm {},
+
+    #[error("Loan {loan_id} does not exist")]
+    LoanNotFound { loan_id: u128 },
+
+    #[error("A flash loan is already in progress")]
+    FlashLoanInProgress {},
+
+    #[error("Flash loan was not repaid with its fee")]
+    FlashLoanNotRepaid {},
+
+    #[error("{action} is paused")]
+    Paused { action: String },
+
+    #[error("Supply cap of {cap} reached for {token}, {remaining} left")]
+    SupplyCapExceeded { token: String, cap: Uint128, remaining: Uint128 },
+
+    #[error("Borrow cap of {cap} reached, {remaining} left")]
+    BorrowCapExceeded { cap: Uint128, remaining: Uint128 },
+
+    #[error("{token} is not enabled as collateral")]
+    CollateralDisabled { token: String },
+
+    #[error("Cannot delegate borrowing to yourself")]
+    CannotDelegateToSelf {},
+
+    #[error("Borrow delegation has expired")]
+    DelegationExpired {},
+
+    #[error("Borrow delegation exceeded, {limit} left")]
+    DelegationExceeded { limit: Uint128 },
+
+    #[error("Cannot migrate: {reason}")]
+    InvalidMigration { reason: String },
+
+    #[error("The {token} token address has not been set")]
+    TokenNotSet { token: String },
     
     // Add any other custom errors you like here.
     // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
@@ -0,0 +1,80 @@
+//! Typed events emitted alongside the `action` attributes, enough for an
+//! indexer to follow every position without querying the contract. Chains
+//! prefix custom event types with `wasm-`.
+//!
+//! Amounts are in token base units. `deposited` is the user's deposit of
+//! `token` after the operation and `debt` what the user owes after it, with
+//! interest accrued up to the block time.
+//!
+//! - `lending/deposit`: user, token, amount, deposited
+//! - `lending/withdraw`: user, recipient, token, amount, deposited, debt.
+//!   Also emitted for collateral seized by a liquidation, sent to the
+//!   liquidator
+//! - `lending/borrow`: user, recipient, loan_id, amount, debt
+//! - `lending/repay`: user, payer, loan_id, amount, debt, one per loan a
+//!   payoff or liquidation pays into
+//! - `lending/config_update`: action, followed by the new value of every
+//!   setting the action changed
+
+use cosmwasm_std::{Addr, Attribute, Event, Uint128};
+
+pub const DEPOSIT: &str = "lending/deposit";
+pub const WITHDRAW: &str = "lending/withdraw";
+pub const BORROW: &str = "lending/borrow";
+pub const REPAY: &str = "lending/repay";
+pub const CONFIG_UPDATE: &str = "lending/config_update";
+
+pub fn deposit(user: &Addr, token: &str, amount: Uint128, deposited: Uint128) -> Event {
+    Event::new(DEPOSIT)
+        .add_attribute("user", user.to_string())
+        .add_attribute("token", token)
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("deposited", deposited.to_string())
+}
+
+pub fn withdraw(
+    user: &Addr,
+    recipient: &Addr,
+    token: &str,
+    amount: Uint128,
+    deposited: Uint128,
+    debt: Uint128,
+) -> Event {
+    Event::new(WITHDRAW)
+        .add_attribute("user", user.to_string())
+        .add_attribute("recipient", recipient.to_string())
+        .add_attribute("token", token)
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("deposited", deposited.to_string())
+        .add_attribute("debt", debt.to_string())
+}
+
+pub fn borrow(user: &Addr, recipient: &Addr, loan_id: Uint128, amount: Uint128, debt: Uint128) -> Event {
+    Event::new(BORROW)
+        .add_attribute("user", user.to_string())
+        .add_attribute("recipient", recipient.to_string())
+        .add_attribute("loan_id", loan_id.to_string())
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("debt", debt.to_string())
+}
+
+/// One event per loan in `allocations`, each with the debt left once the
+/// whole payment is applied
+pub fn repay(user: &Addr, payer: &Addr, allocations: &[(u128, Uint128)], debt: Uint128) -> Vec<Event> {
+    allocations.iter()
+        .map(|(loan_id, amount)| {
+            Event::new(REPAY)
+                .add_attribute("user", user.to_string())
+                .add_attribute("payer", payer.to_string())
+                .add_attribute("loan_id", loan_id.to_string())
+                .add_attribute("amount", amount.to_string())
+                .add_attribute("debt", debt.to_string())
+        })
+        .collect()
+}
+
+pub fn config_update(action: &str, attributes: Vec<Attribute>) -> Event {
+    Event::new(CONFIG_UPDATE)
+        .add_attribute("action", action)
+        .add_attributes(attributes)
+}
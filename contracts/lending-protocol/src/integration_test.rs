@@ -1,17 +1,23 @@
 #![cfg(test)]
 
-use cosmwasm_std::{coins, to_binary, Addr, Empty, Uint128, Timestamp, BlockInfo};
-use cw20::{Cw20Coin, Cw20Contract, Cw20ExecuteMsg, MinterResponse};
-use cw_multi_test::{App, Contract, ContractWrapper, Executor};
+use cosmwasm_std::{coin, coins, from_slice, to_binary, Addr, Decimal, Empty, Uint128};
+use cw20::{Cw20Coin, Cw20Contract, Cw20ExecuteMsg, Expiration, MinterResponse};
+use cw_multi_test::{App, AppResponse, Contract, ContractWrapper, Executor};
 
-use crate::{msg::{ExecuteMsg, InstantiateMsg, QueryMsg, Cw20HookMsg, UserInfoResponse}};
+use crate::{msg::{ExecuteMsg, InstantiateMsg, QueryMsg, Cw20HookMsg, UserInfoResponse, InterestRateResponse, SupplyInfoResponse, MarketsResponse, ReservesResponse, AdminResponse, RoleInfo, RolesResponse, CapacityResponse, MarketCapacity, AccountLiquidityResponse, AllUsersResponse, LoansResponse, ConfigResponse, MarketStateResponse, SimulatePayoffResponse, LoanPayment, RepaymentPolicy, ProjectedDebtResponse, ProjectedLoan, BorrowDelegationsResponse, BorrowDelegationInfo, DepositPrice}};
+use crate::events;
+use crate::interest_rate::{FixedRateModel, InterestRateModelConfig, JumpRateModel};
+use crate::mock_flash_receiver::{contract_mock_flash_receiver, MockFlashReceiverExecuteMsg};
+use crate::mock_oracle::{contract_mock_oracle, MockOracleExecuteMsg};
+use crate::oracle::PriceResponse;
+use crate::state::{CollateralDeposit, MarketState, PauseState, Role, UserData, MARKET_STATE, TERM_LOANS, USER_INFO};
 
 pub fn contract_lending_protocol() -> Box<dyn Contract<Empty>> {
     let contract = ContractWrapper::new(
         crate::contract::execute,
         crate::contract::instantiate,
         crate::contract::query,
-    );
+    ).with_reply(crate::contract::reply);
     Box::new(contract)
 }
 
@@ -34,50 +40,60 @@ pub fn contract_lending_token() -> Box<dyn Contract<Empty>> {
     Box::new(contract)
 }
 
-#[test]
-fn integration() {
-    // init vars
-    let admin = Addr::unchecked("admin");
-
-    // set personal balance
-    let user1 = Addr::unchecked("user1");
-    let init_funds = coins(2000, "btc");
-
-    let mut router = App::new(|router, _, storage| {
-        router
-            .bank
-            .init_balance(storage, &user1, init_funds)
-            .unwrap();
-    });
-
+/// Instantiates the generic token (funded with `initial_balances`), a mock
+/// oracle pricing it at 1, the lending protocol and the lending token, and
+/// wires them together.
+/// Returns (generic token, lending protocol, lending token, oracle) addresses.
+fn setup_contracts(router: &mut App, admin: &Addr, initial_balances: Vec<Cw20Coin>) -> (Addr, Addr, Addr, Addr) {
     // setup generic token contract
     let generic_id = router.store_code(contract_generic_cw20());
     let msg = cw20_base::msg::InstantiateMsg {
         name: "generic".to_string(),
         symbol: "GEN".to_string(),
         decimals: 6,
-        initial_balances: vec![Cw20Coin {
-            address: user1.to_string(),
-            amount: Uint128::new(5000_u128.pow(6)),
-        }],
+        initial_balances,
         mint: None,
         marketing: None,
     };
     let generic_addr = router.instantiate_contract(
         generic_id,
-        user1.clone(), 
+        admin.clone(), 
         &msg, 
         &[], 
         "GENERIC", 
         None
     ).unwrap();
-    let generic_token_contract = Cw20Contract(generic_addr.clone());
+
+    // setup oracle contract
+    let oracle_id = router.store_code(contract_mock_oracle());
+    let oracle_addr = router.instantiate_contract(
+        oracle_id,
+        admin.clone(), 
+        &Empty {}, 
+        &[], 
+        "ORACLE", 
+        None
+    ).unwrap();
+    set_price(router, admin, &oracle_addr, &generic_addr, Decimal::one());
 
     // setup lending protocol contract
     let lending_protocol_id = router.store_code(contract_lending_protocol());
     let msg = InstantiateMsg { 
         admin: admin.clone().into_string(), 
-        generic_token: generic_addr.clone().into_string()
+        generic_token: generic_addr.clone().into_string(),
+        oracle: oracle_addr.clone().into_string(),
+        max_price_age: 3600,
+        collateral_factor: Decimal::one(),
+        liquidation_threshold: Decimal::one(),
+        liquidation_bonus: Decimal::percent(5),
+        close_factor: Decimal::percent(50),
+        reserve_factor: Decimal::zero(),
+        treasury: admin.to_string(),
+        term_loan_rate: Decimal::percent(5),
+        penalty_rate: Decimal::percent(20),
+        grace_period: 86400 * 7,
+        flash_loan_fee: Decimal::percent(1),
+        interest_rate_model: InterestRateModelConfig::Fixed(FixedRateModel { rate: Decimal::percent(3) }),
     };
     let lending_protocol_addr = router.instantiate_contract(
         lending_protocol_id,
@@ -104,18 +120,97 @@ fn integration() {
     };
     let lending_addr = router.instantiate_contract(
         lending_id,
-        user1.clone(), 
+        admin.clone(), 
         &msg, 
         &[], 
         "GENERIC", 
         None
     ).unwrap();
-    let lending_token_contract = Cw20Contract(lending_addr.clone());
 
     // setup lending token address in lending protocol
     let msg = ExecuteMsg::SetLendingTokenAddress { address: lending_addr.clone().into_string() };
     router.execute_contract(admin.clone(), lending_protocol_addr.clone(), &msg, &[]).unwrap();
 
+    (generic_addr, lending_protocol_addr, lending_addr, oracle_addr)
+}
+
+/// Instantiates a receipt token minted by the lending protocol and registers
+/// it as the protocol's receipt token.
+fn setup_receipt_token(router: &mut App, admin: &Addr, lending_protocol_addr: &Addr) -> Addr {
+    let receipt_id = router.store_code(contract_lending_token());
+    let msg = lending_token::msg::InstantiateMsg {
+        name: "receipt".to_string(),
+        symbol: "RLEN".to_string(),
+        decimals: 6,
+        initial_balances: vec![],
+        mint: Some(
+            MinterResponse{ 
+                minter: lending_protocol_addr.clone().into_string(), 
+                cap: None }
+            ),
+        marketing: None,
+    };
+    let receipt_addr = router.instantiate_contract(
+        receipt_id,
+        admin.clone(), 
+        &msg, 
+        &[], 
+        "RECEIPT", 
+        None
+    ).unwrap();
+
+    let msg = ExecuteMsg::SetReceiptTokenAddress { address: receipt_addr.clone().into_string() };
+    router.execute_contract(admin.clone(), lending_protocol_addr.clone(), &msg, &[]).unwrap();
+    receipt_addr
+}
+
+fn set_price(router: &mut App, admin: &Addr, oracle_addr: &Addr, asset: &Addr, price: Decimal) {
+    let msg = MockOracleExecuteMsg::SetPrice { asset: asset.to_string(), price };
+    router.execute_contract(admin.clone(), oracle_addr.clone(), &msg, &[]).unwrap();
+}
+
+/// attributes of each `ty` event the protocol emitted, without the contract
+/// address the chain adds
+fn lending_events(res: &AppResponse, ty: &str) -> Vec<Vec<(String, String)>> {
+    res.events.iter()
+        .filter(|event| event.ty == format!("wasm-{}", ty))
+        .map(|event| {
+            event.attributes.iter()
+                .filter(|attr| attr.key != "_contract_addr")
+                .map(|attr| (attr.key.clone(), attr.value.clone()))
+                .collect()
+        })
+        .collect()
+}
+
+fn attrs(attributes: &[(&str, &str)]) -> Vec<(String, String)> {
+    attributes.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+}
+
+#[test]
+fn integration() {
+    // init vars
+    let admin = Addr::unchecked("admin");
+
+    // set personal balance
+    let user1 = Addr::unchecked("user1");
+    let init_funds = coins(2000, "btc");
+
+    let mut router = App::new(|router, _, storage| {
+        router
+            .bank
+            .init_balance(storage, &user1, init_funds)
+            .unwrap();
+    });
+
+    let (generic_addr, lending_protocol_addr, lending_addr, _) =
+        setup_contracts(&mut router, &admin, vec![Cw20Coin {
+            address: user1.to_string(),
+            amount: Uint128::new(5000_u128.pow(6)),
+        }]);
+    let generic_token_contract = Cw20Contract(generic_addr.clone());
+    let lending_token_contract = Cw20Contract(lending_addr.clone());
+
     /*
      * user1 deposit generic token into lending protocol
      */
@@ -136,7 +231,7 @@ fn integration() {
      * user1 requests to borrow 1 lending token
      */
     let borrow_amt = 1000_u128.pow(6);
-    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::from(borrow_amt) };
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::from(borrow_amt), term: None, on_behalf_of: None };
     router.execute_contract(user1.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
     // check lending tokens have been minted to user1
     let balance = lending_token_contract.balance::<_, _, Empty>(&router, user1.clone()).unwrap();
@@ -149,7 +244,7 @@ fn integration() {
      * user1 trys to withdraw more that allowed, due to collateral on loan
      */
     let withdraw_amt = 4000_u128.pow(6);
-    let withdraw_msg = ExecuteMsg::Withdraw { amount: Uint128::from(withdraw_amt) };
+    let withdraw_msg = ExecuteMsg::Withdraw { token: generic_addr.to_string(), amount: Uint128::from(withdraw_amt) };
     router.execute_contract(user1.clone(), lending_protocol_addr.clone(), &withdraw_msg, &[]).unwrap_err();
     // check contract still has 4000_u128 in account
     let balance = generic_token_contract.balance::<_, _, Empty>(&router, lending_protocol_addr.clone()).unwrap();
@@ -162,7 +257,7 @@ fn integration() {
      * user1 trys allowed withdrawal
      */
     let withdraw_amt = 3000_u128.pow(6);
-    let withdraw_msg = ExecuteMsg::Withdraw { amount: Uint128::from(withdraw_amt) };
+    let withdraw_msg = ExecuteMsg::Withdraw { token: generic_addr.to_string(), amount: Uint128::from(withdraw_amt) };
     router.execute_contract(user1.clone(), lending_protocol_addr.clone(), &withdraw_msg, &[]).unwrap();
     // check contract has right amount left in its account
     let balance = generic_token_contract.balance::<_, _, Empty>(&router, lending_protocol_addr.clone()).unwrap();
@@ -183,3 +278,1585 @@ fn integration() {
         None => panic!("User should exist!")
     };
 }
+
+
+#[test]
+fn liquidation() {
+    let admin = Addr::unchecked("admin");
+    let borrower = Addr::unchecked("borrower");
+    let liquidator = Addr::unchecked("liquidator");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, lending_addr, oracle_addr) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: borrower.to_string(), amount: Uint128::new(1000) },
+            Cw20Coin { address: liquidator.to_string(), amount: Uint128::new(1000) },
+        ]);
+    let generic_token_contract = Cw20Contract(generic_addr.clone());
+    let lending_token_contract = Cw20Contract(lending_addr.clone());
+
+    // both users deposit, borrower borrows against their full deposit
+    for user in [&borrower, &liquidator] {
+        let send_msg = Cw20ExecuteMsg::Send { 
+            contract: lending_protocol_addr.to_string(), 
+            amount: Uint128::new(1000), 
+            msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+        };
+        router.execute_contract(user.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    }
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1000), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(500), term: None, on_behalf_of: None };
+    router.execute_contract(liquidator.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+
+    /*
+     * position is still healthy, liquidation must fail
+     */
+    let liquidate_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(100), 
+        msg: to_binary(&Cw20HookMsg::Liquidate { borrower: borrower.to_string(), collateral: generic_addr.to_string() }).unwrap()
+    };
+    router.execute_contract(liquidator.clone(), lending_addr.clone(), &liquidate_msg, &[]).unwrap_err();
+
+    /*
+     * a year of interest pushes debt (1030) above collateral (1000)
+     */
+    router.update_block(|block| block.time = block.time.plus_seconds(86400 * 365));
+    set_price(&mut router, &admin, &oracle_addr, &generic_addr, Decimal::one());
+
+    // repaying more than the close factor allows refunds the excess
+    let liquidate_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(500), 
+        msg: to_binary(&Cw20HookMsg::Liquidate { borrower: borrower.to_string(), collateral: generic_addr.to_string() }).unwrap()
+    };
+    router.execute_contract(liquidator.clone(), lending_addr.clone(), &liquidate_msg, &[]).unwrap();
+
+    // close factor 50% of 1030 => 515 repaid, capped by the 500 sent
+    let balance = lending_token_contract.balance::<_, _, Empty>(&router, liquidator.clone()).unwrap();
+    assert_eq!(balance.u128(), 0);
+    // 500 repaid + 5% bonus => 525 collateral seized
+    let balance = generic_token_contract.balance::<_, _, Empty>(&router, liquidator.clone()).unwrap();
+    assert_eq!(balance.u128(), 525);
+
+    let query_msg = QueryMsg::GetUserInfo { address: borrower.to_string() };
+    let user_info: Option<UserInfoResponse> = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &query_msg).unwrap();
+    let user_info = user_info.unwrap();
+    assert_eq!(user_info.deposits[0].amount.u128(), 475);
+    assert_eq!(user_info.total_loan_owed.u128(), 530);
+
+    /*
+     * liquidation through the allowance path
+     */
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(100), term: None, on_behalf_of: None };
+    router.execute_contract(liquidator.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let allowance_msg = Cw20ExecuteMsg::IncreaseAllowance { 
+        spender: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(100), 
+        expires: None,
+    };
+    router.execute_contract(liquidator.clone(), lending_addr.clone(), &allowance_msg, &[]).unwrap();
+    let liquidate_msg = ExecuteMsg::Liquidate { borrower: borrower.to_string(), collateral: generic_addr.to_string(), amount: Uint128::new(100) };
+    router.execute_contract(liquidator.clone(), lending_protocol_addr.clone(), &liquidate_msg, &[]).unwrap();
+
+    let balance = lending_token_contract.balance::<_, _, Empty>(&router, liquidator.clone()).unwrap();
+    assert_eq!(balance.u128(), 0);
+    let balance = generic_token_contract.balance::<_, _, Empty>(&router, liquidator).unwrap();
+    assert_eq!(balance.u128(), 525 + 105);
+}
+
+#[test]
+fn oracle_pricing() {
+    let admin = Addr::unchecked("admin");
+    let borrower = Addr::unchecked("borrower");
+    let liquidator = Addr::unchecked("liquidator");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, lending_addr, oracle_addr) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: borrower.to_string(), amount: Uint128::new(1000) },
+            Cw20Coin { address: liquidator.to_string(), amount: Uint128::new(2000) },
+        ]);
+    let generic_token_contract = Cw20Contract(generic_addr.clone());
+
+    for (user, amount) in [(&borrower, 1000), (&liquidator, 2000)] {
+        let send_msg = Cw20ExecuteMsg::Send { 
+            contract: lending_protocol_addr.to_string(), 
+            amount: Uint128::new(amount), 
+            msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+        };
+        router.execute_contract(user.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    }
+
+    /*
+     * collateral worth 2 lending tokens each doubles the borrow limit
+     */
+    set_price(&mut router, &admin, &oracle_addr, &generic_addr, Decimal::percent(200));
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(2001), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap_err();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(2000), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+
+    let query_msg = QueryMsg::GetUserInfo { address: borrower.to_string() };
+    let user_info: Option<UserInfoResponse> = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &query_msg).unwrap();
+    let user_info = user_info.unwrap();
+    assert_eq!(user_info.collateral_value.u128(), 2000);
+    assert_eq!(user_info.prices, vec![DepositPrice { token: generic_addr.to_string(), price: Decimal::percent(200), stale: false }]);
+
+    /*
+     * stale prices are rejected
+     */
+    router.update_block(|block| block.time = block.time.plus_seconds(3601));
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1000), term: None, on_behalf_of: None };
+    router.execute_contract(liquidator.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap_err();
+    let query_msg = QueryMsg::CollateralPrice { token: generic_addr.to_string() };
+    router.wrap().query_wasm_smart::<PriceResponse>(lending_protocol_addr.clone(), &query_msg).unwrap_err();
+
+    // the position stays readable at the last accepted price
+    let query_msg = QueryMsg::GetUserInfo { address: borrower.to_string() };
+    let user_info: Option<UserInfoResponse> = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &query_msg).unwrap();
+    let user_info = user_info.unwrap();
+    assert_eq!(user_info.collateral_value.u128(), 2000);
+    assert!(user_info.price_stale);
+    assert_eq!(user_info.prices, vec![DepositPrice { token: generic_addr.to_string(), price: Decimal::percent(200), stale: true }]);
+
+    /*
+     * price drop makes the borrower liquidatable
+     */
+    set_price(&mut router, &admin, &oracle_addr, &generic_addr, Decimal::percent(150));
+    let query_msg = QueryMsg::CollateralPrice { token: generic_addr.to_string() };
+    let price: PriceResponse = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &query_msg).unwrap();
+    assert_eq!(price.price, Decimal::percent(150));
+
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1000), term: None, on_behalf_of: None };
+    router.execute_contract(liquidator.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let liquidate_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(600), 
+        msg: to_binary(&Cw20HookMsg::Liquidate { borrower: borrower.to_string(), collateral: generic_addr.to_string() }).unwrap()
+    };
+    router.execute_contract(liquidator.clone(), lending_addr, &liquidate_msg, &[]).unwrap();
+
+    // 600 repaid + 5% bonus at 1.5 lending tokens per generic token => 420 seized
+    let balance = generic_token_contract.balance::<_, _, Empty>(&router, liquidator).unwrap();
+    assert_eq!(balance.u128(), 420);
+}
+
+#[test]
+fn variable_interest_rate() {
+    let admin = Addr::unchecked("admin");
+    let user1 = Addr::unchecked("user1");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, _, oracle_addr) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: user1.to_string(), amount: Uint128::new(1000) },
+        ]);
+
+    let model = InterestRateModelConfig::JumpRate(JumpRateModel {
+        base_rate: Decimal::percent(2),
+        multiplier: Decimal::percent(10),
+        jump_multiplier: Decimal::percent(200),
+        kink: Decimal::percent(80),
+    });
+    let msg = ExecuteMsg::UpdateInterestRateModel { model: model.clone() };
+    router.execute_contract(user1.clone(), lending_protocol_addr.clone(), &msg, &[]).unwrap_err();
+    router.execute_contract(admin.clone(), lending_protocol_addr.clone(), &msg, &[]).unwrap();
+
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(1000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    router.execute_contract(user1.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+
+    // rate follows utilization as the user borrows
+    let mut rates = vec![];
+    for _ in 0..3 {
+        let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(300), term: None, on_behalf_of: None };
+        router.execute_contract(user1.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+        let res: InterestRateResponse = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &QueryMsg::InterestRate {}).unwrap();
+        assert_eq!(res.model, model);
+        rates.push((res.utilization, res.borrow_rate));
+    }
+    assert_eq!(rates, vec![
+        (Decimal::percent(30), Decimal::percent(5)),
+        (Decimal::percent(60), Decimal::percent(8)),
+        (Decimal::percent(90), Decimal::percent(30)),
+    ]);
+
+    // a year at 30% on the 900 borrowed
+    router.update_block(|block| block.time = block.time.plus_seconds(86400 * 365));
+    set_price(&mut router, &admin, &oracle_addr, &generic_addr, Decimal::one());
+    let query_msg = QueryMsg::GetUserInfo { address: user1.to_string() };
+    let user_info: Option<UserInfoResponse> = router.wrap().query_wasm_smart(lending_protocol_addr, &query_msg).unwrap();
+    assert_eq!(user_info.unwrap().total_loan_owed.u128(), 1170);
+}
+
+#[test]
+fn supply_receipts() {
+    let admin = Addr::unchecked("admin");
+    let borrower = Addr::unchecked("borrower");
+    let supplier = Addr::unchecked("supplier");
+    let late = Addr::unchecked("late");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, lending_addr, oracle_addr) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: borrower.to_string(), amount: Uint128::new(1000) },
+            Cw20Coin { address: supplier.to_string(), amount: Uint128::new(1000) },
+            Cw20Coin { address: late.to_string(), amount: Uint128::new(1000) },
+        ]);
+    for (user, amount) in [(&supplier, 500), (&late, 300)] {
+        let send_msg = Cw20ExecuteMsg::Send { 
+            contract: lending_protocol_addr.to_string(), 
+            amount: Uint128::new(1000), 
+            msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+        };
+        router.execute_contract(user.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+        let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(amount), term: None, on_behalf_of: None };
+        router.execute_contract(user.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    }
+
+    // supplying is rejected until the receipt token is set
+    let supply_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(500), 
+        msg: to_binary(&Cw20HookMsg::Supply {}).unwrap()
+    };
+    let err = router.execute_contract(supplier.clone(), lending_addr.clone(), &supply_msg, &[]).unwrap_err();
+    assert_eq!(err.root_cause().to_string(), "The receipt token address has not been set");
+
+    let receipt_addr = setup_receipt_token(&mut router, &admin, &lending_protocol_addr);
+    let lending_token_contract = Cw20Contract(lending_addr.clone());
+    let receipt_token_contract = Cw20Contract(receipt_addr.clone());
+
+    // receipts can only be minted by sending lending tokens
+    let supply_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(500), 
+        msg: to_binary(&Cw20HookMsg::Supply {}).unwrap()
+    };
+    router.execute_contract(supplier.clone(), generic_addr.clone(), &supply_msg, &[]).unwrap_err();
+    router.execute_contract(supplier.clone(), lending_addr.clone(), &supply_msg, &[]).unwrap();
+    let balance = receipt_token_contract.balance::<_, _, Empty>(&router, supplier.clone()).unwrap();
+    assert_eq!(balance.u128(), 500);
+
+    /*
+     * the borrower's loan is lent out of the 500 supplied and minted for the rest
+     */
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(1000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    router.execute_contract(borrower.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1000), term: None, on_behalf_of: None };
+    let res = router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let transfers: Vec<_> = res.events.iter()
+        .filter(|event| event.ty == "wasm")
+        .filter_map(|event| {
+            let action = event.attributes.iter().find(|attr| attr.key == "action")?;
+            let amount = event.attributes.iter().find(|attr| attr.key == "amount")?;
+            Some((action.value.clone(), amount.value.clone()))
+        })
+        .filter(|(action, _)| action == "transfer" || action == "mint")
+        .collect();
+    assert_eq!(transfers, vec![("transfer".to_string(), "500".to_string()), ("mint".to_string(), "500".to_string())]);
+    let balance = lending_token_contract.balance::<_, _, Empty>(&router, lending_protocol_addr.clone()).unwrap();
+    assert_eq!(balance.u128(), 0);
+
+    /*
+     * a year later the pool has earned 3% on the 500 it lent, before anything is repaid
+     */
+    router.update_block(|block| block.time = block.time.plus_seconds(86400 * 365));
+    set_price(&mut router, &admin, &oracle_addr, &generic_addr, Decimal::one());
+    let supply_info: SupplyInfoResponse = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &QueryMsg::SupplyInfo {}).unwrap();
+    assert_eq!(supply_info.receipt_token, Some(receipt_addr.clone()));
+    assert_eq!(supply_info.total_supply.u128(), 515);
+    assert_eq!(supply_info.total_receipts.u128(), 500);
+    assert_eq!(supply_info.exchange_rate, Decimal::percent(103));
+
+    // supplying just before a repayment buys receipts at the accrued rate
+    let supply_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(300), 
+        msg: to_binary(&Cw20HookMsg::Supply {}).unwrap()
+    };
+    router.execute_contract(late.clone(), lending_addr.clone(), &supply_msg, &[]).unwrap();
+    let late_receipts = receipt_token_contract.balance::<_, _, Empty>(&router, late.clone()).unwrap();
+    assert_eq!(late_receipts.u128(), 291);
+
+    let payoff_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(1000), 
+        msg: to_binary(&Cw20HookMsg::Payoff { loan_id: None, policy: None }).unwrap()
+    };
+    router.execute_contract(borrower.clone(), lending_addr.clone(), &payoff_msg, &[]).unwrap();
+
+    let query_msg = QueryMsg::GetUserInfo { address: borrower.to_string() };
+    let user_info: Option<UserInfoResponse> = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &query_msg).unwrap();
+    assert_eq!(user_info.unwrap().total_loan_owed.u128(), 30);
+
+    /*
+     * so the repayment gains the late supplier nothing, the interest is the first supplier's
+     */
+    let redeem_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: late_receipts, 
+        msg: to_binary(&Cw20HookMsg::Redeem {}).unwrap()
+    };
+    router.execute_contract(late.clone(), receipt_addr.clone(), &redeem_msg, &[]).unwrap();
+    let balance = lending_token_contract.balance::<_, _, Empty>(&router, late).unwrap();
+    assert_eq!(balance.u128(), 299);
+
+    // redeeming receipts pays out the supplied tokens plus interest
+    let redeem_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(500), 
+        msg: to_binary(&Cw20HookMsg::Redeem {}).unwrap()
+    };
+    router.execute_contract(supplier.clone(), receipt_addr, &redeem_msg, &[]).unwrap();
+    let balance = receipt_token_contract.balance::<_, _, Empty>(&router, supplier.clone()).unwrap();
+    assert_eq!(balance.u128(), 0);
+    let balance = lending_token_contract.balance::<_, _, Empty>(&router, supplier).unwrap();
+    assert_eq!(balance.u128(), 516);
+    let balance = lending_token_contract.balance::<_, _, Empty>(&router, lending_protocol_addr).unwrap();
+    assert_eq!(balance.u128(), 0);
+}
+
+#[test]
+fn multi_collateral() {
+    let admin = Addr::unchecked("admin");
+    let borrower = Addr::unchecked("borrower");
+    let liquidator = Addr::unchecked("liquidator");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, lending_addr, oracle_addr) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: borrower.to_string(), amount: Uint128::new(1000) },
+            Cw20Coin { address: liquidator.to_string(), amount: Uint128::new(1000) },
+        ]);
+
+    // second collateral token, priced at 4 lending tokens
+    let other_id = router.store_code(contract_generic_cw20());
+    let msg = cw20_base::msg::InstantiateMsg {
+        name: "other".to_string(),
+        symbol: "OTH".to_string(),
+        decimals: 6,
+        initial_balances: vec![Cw20Coin { address: borrower.to_string(), amount: Uint128::new(500) }],
+        mint: None,
+        marketing: None,
+    };
+    let other_addr = router.instantiate_contract(other_id, admin.clone(), &msg, &[], "OTHER", None).unwrap();
+    let other_token_contract = Cw20Contract(other_addr.clone());
+    set_price(&mut router, &admin, &oracle_addr, &other_addr, Decimal::percent(400));
+
+    // deposits of unlisted tokens are rejected
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(500), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    router.execute_contract(borrower.clone(), other_addr.clone(), &send_msg, &[]).unwrap_err();
+
+    let list_msg = ExecuteMsg::ListMarket {
+        token: other_addr.to_string(),
+        native: false,
+        collateral_factor: Decimal::percent(50),
+        liquidation_threshold: Decimal::percent(60),
+        liquidation_bonus: Decimal::percent(10),
+        supply_cap: None,
+    };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &list_msg, &[]).unwrap_err();
+    router.execute_contract(admin.clone(), lending_protocol_addr.clone(), &list_msg, &[]).unwrap();
+    router.execute_contract(borrower.clone(), other_addr.clone(), &send_msg, &[]).unwrap();
+    for user in [&borrower, &liquidator] {
+        let send_msg = Cw20ExecuteMsg::Send { 
+            contract: lending_protocol_addr.to_string(), 
+            amount: Uint128::new(1000), 
+            msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+        };
+        router.execute_contract(user.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    }
+
+    /*
+     * borrow limit combines 1000 generic at 100% and 2000 worth of other at 50%
+     */
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(2001), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap_err();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(2000), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let withdraw_msg = ExecuteMsg::Withdraw { token: other_addr.to_string(), amount: Uint128::new(1) };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &withdraw_msg, &[]).unwrap_err();
+
+    let query_msg = QueryMsg::GetUserInfo { address: borrower.to_string() };
+    let user_info: Option<UserInfoResponse> = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &query_msg).unwrap();
+    assert_eq!(user_info.unwrap().collateral_value.u128(), 3000);
+    let markets: MarketsResponse = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &QueryMsg::Markets {}).unwrap();
+    assert_eq!(markets.markets.len(), 2);
+    assert_eq!(markets.markets.iter().map(|m| m.total_deposits.u128()).sum::<u128>(), 2500);
+
+    /*
+     * other halving in price drops the liquidation limit to 1000 + 1000 * 60%
+     */
+    set_price(&mut router, &admin, &oracle_addr, &other_addr, Decimal::percent(200));
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(500), term: None, on_behalf_of: None };
+    router.execute_contract(liquidator.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let liquidate_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(400), 
+        msg: to_binary(&Cw20HookMsg::Liquidate { borrower: borrower.to_string(), collateral: other_addr.to_string() }).unwrap()
+    };
+    router.execute_contract(liquidator.clone(), lending_addr, &liquidate_msg, &[]).unwrap();
+
+    // 400 repaid + 10% bonus at 2 lending tokens per other token => 220 seized
+    let balance = other_token_contract.balance::<_, _, Empty>(&router, liquidator).unwrap();
+    assert_eq!(balance.u128(), 220);
+}
+
+#[test]
+fn native_collateral() {
+    let admin = Addr::unchecked("admin");
+    let borrower = Addr::unchecked("borrower");
+    let mut router = App::new(|router, _, storage| {
+        router
+            .bank
+            .init_balance(storage, &borrower, vec![coin(1000, "uatom"), coin(1000, "uosmo")])
+            .unwrap();
+    });
+
+    let (_, lending_protocol_addr, lending_addr, oracle_addr) = setup_contracts(&mut router, &admin, vec![]);
+    let list_msg = ExecuteMsg::ListMarket {
+        token: "uatom".to_string(),
+        native: true,
+        collateral_factor: Decimal::percent(50),
+        liquidation_threshold: Decimal::percent(60),
+        liquidation_bonus: Decimal::percent(5),
+        supply_cap: None,
+    };
+    router.execute_contract(admin.clone(), lending_protocol_addr.clone(), &list_msg, &[]).unwrap();
+    router.execute_contract(admin.clone(), oracle_addr.clone(), &MockOracleExecuteMsg::SetPrice {
+        asset: "uatom".to_string(),
+        price: Decimal::one(),
+    }, &[]).unwrap();
+
+    // risk parameters can be updated but not the kind of token
+    let list_msg = ExecuteMsg::ListMarket {
+        token: "uatom".to_string(),
+        native: false,
+        collateral_factor: Decimal::percent(50),
+        liquidation_threshold: Decimal::percent(60),
+        liquidation_bonus: Decimal::percent(5),
+        supply_cap: None,
+    };
+    let err = router.execute_contract(admin.clone(), lending_protocol_addr.clone(), &list_msg, &[]).unwrap_err();
+    assert_eq!(err.root_cause().to_string(), "Invalid config: native cannot change for a listed market");
+
+    // only listed denoms can be deposited
+    let deposit_msg = ExecuteMsg::DepositNative {};
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &deposit_msg, &coins(1000, "uosmo")).unwrap_err();
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &deposit_msg, &coins(1000, "uatom")).unwrap();
+    assert_eq!(router.wrap().query_balance(borrower.to_string(), "uatom").unwrap().amount.u128(), 0);
+
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(500), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let withdraw_msg = ExecuteMsg::Withdraw { token: "uatom".to_string(), amount: Uint128::new(1) };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &withdraw_msg, &[]).unwrap_err();
+
+    // once the loan is repaid the coins are sent back through the bank module
+    let payoff_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(500), 
+        msg: to_binary(&Cw20HookMsg::Payoff { loan_id: None, policy: None }).unwrap()
+    };
+    router.execute_contract(borrower.clone(), lending_addr, &payoff_msg, &[]).unwrap();
+    let withdraw_msg = ExecuteMsg::Withdraw { token: "uatom".to_string(), amount: Uint128::new(1000) };
+    router.execute_contract(borrower.clone(), lending_protocol_addr, &withdraw_msg, &[]).unwrap();
+    assert_eq!(router.wrap().query_balance(borrower.to_string(), "uatom").unwrap().amount.u128(), 1000);
+}
+
+#[test]
+fn reserves() {
+    let admin = Addr::unchecked("admin");
+    let borrower = Addr::unchecked("borrower");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, lending_addr, oracle_addr) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: borrower.to_string(), amount: Uint128::new(2000) },
+        ]);
+    let lending_token_contract = Cw20Contract(lending_addr.clone());
+
+    let update_msg = ExecuteMsg::UpdateConfig {
+        oracle: None,
+        max_price_age: None,
+        close_factor: None,
+        reserve_factor: Some(Decimal::percent(20)),
+        treasury: None,
+        term_loan_rate: None,
+        penalty_rate: None,
+        grace_period: None,
+        flash_loan_fee: None,
+    };
+    router.execute_contract(admin.clone(), lending_protocol_addr.clone(), &update_msg, &[]).unwrap();
+
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(2000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    router.execute_contract(borrower.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1000), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+
+    /*
+     * a year later the first loan owes 30 of interest, 20% of it is kept
+     */
+    router.update_block(|block| block.time = block.time.plus_seconds(86400 * 365));
+    set_price(&mut router, &admin, &oracle_addr, &generic_addr, Decimal::one());
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(30), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let payoff_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(1030), 
+        msg: to_binary(&Cw20HookMsg::Payoff { loan_id: None, policy: None }).unwrap()
+    };
+    router.execute_contract(borrower.clone(), lending_addr.clone(), &payoff_msg, &[]).unwrap();
+
+    let res: ReservesResponse = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &QueryMsg::Reserves {}).unwrap();
+    assert_eq!(res, ReservesResponse {
+        treasury: admin.clone(),
+        reserve_factor: Decimal::percent(20),
+        total_reserves: Uint128::new(6),
+    });
+    // reserves are held by the protocol, the rest of the repayment is burned
+    let balance = lending_token_contract.balance::<_, _, Empty>(&router, lending_protocol_addr.clone()).unwrap();
+    assert_eq!(balance.u128(), 6);
+
+    /*
+     * only the admin can withdraw, and no more than the reserves
+     */
+    let withdraw_msg = ExecuteMsg::WithdrawReserves { amount: Uint128::new(6), recipient: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &withdraw_msg, &[]).unwrap_err();
+    let too_much_msg = ExecuteMsg::WithdrawReserves { amount: Uint128::new(7), recipient: None };
+    router.execute_contract(admin.clone(), lending_protocol_addr.clone(), &too_much_msg, &[]).unwrap_err();
+    router.execute_contract(admin.clone(), lending_protocol_addr.clone(), &withdraw_msg, &[]).unwrap();
+    let balance = lending_token_contract.balance::<_, _, Empty>(&router, admin).unwrap();
+    assert_eq!(balance.u128(), 6);
+}
+
+#[test]
+fn term_loan() {
+    let admin = Addr::unchecked("admin");
+    let borrower = Addr::unchecked("borrower");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, lending_addr, oracle_addr) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: borrower.to_string(), amount: Uint128::new(2000) },
+        ]);
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(2000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    router.execute_contract(borrower.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1000), term: Some(0), on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap_err();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1000), term: Some(86400 * 365), on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+
+    /*
+     * 445 days at the 5% term rate, plus 20% for the 73 days past maturity and
+     * the 7 day grace period
+     */
+    router.update_block(|block| block.time = block.time.plus_seconds(86400 * 445));
+    set_price(&mut router, &admin, &oracle_addr, &generic_addr, Decimal::one());
+    let query_msg = QueryMsg::GetUserInfo { address: borrower.to_string() };
+    let user_info: Option<UserInfoResponse> = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &query_msg).unwrap();
+    assert_eq!(user_info.unwrap().total_loan_owed.u128(), 1000 + 60 + 40);
+
+    // repayments cover the interest before the principal
+    let payoff_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(1000), 
+        msg: to_binary(&Cw20HookMsg::Payoff { loan_id: None, policy: None }).unwrap()
+    };
+    router.execute_contract(borrower.clone(), lending_addr.clone(), &payoff_msg, &[]).unwrap();
+    let user_info: Option<UserInfoResponse> = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &query_msg).unwrap();
+    assert_eq!(user_info.unwrap().total_loan_owed.u128(), 100);
+
+    // a closed term loan leaves the term loan index, variable debt is still counted
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(100), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let payoff_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(100), 
+        msg: to_binary(&Cw20HookMsg::Payoff { loan_id: Some(Uint128::zero()), policy: None }).unwrap()
+    };
+    router.execute_contract(borrower.clone(), lending_addr, &payoff_msg, &[]).unwrap();
+    let raw = router.wrap().query_wasm_raw(lending_protocol_addr.clone(), TERM_LOANS.key((&borrower, 0)).to_vec()).unwrap();
+    assert_eq!(raw, None);
+    let user_info: Option<UserInfoResponse> = router.wrap().query_wasm_smart(lending_protocol_addr, &query_msg).unwrap();
+    assert_eq!(user_info.unwrap().total_loan_owed.u128(), 100);
+}
+
+#[test]
+fn deposit_and_payoff_for() {
+    let admin = Addr::unchecked("admin");
+    let borrower = Addr::unchecked("borrower");
+    let vault = Addr::unchecked("vault");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, lending_addr, _) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: vault.to_string(), amount: Uint128::new(4000) },
+        ]);
+    let lending_token_contract = Cw20Contract(lending_addr.clone());
+
+    /*
+     * the vault funds the borrower's collateral and its own
+     */
+    let deposit_for_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(2000), 
+        msg: to_binary(&Cw20HookMsg::DepositFor { beneficiary: borrower.to_string() }).unwrap()
+    };
+    router.execute_contract(vault.clone(), generic_addr.clone(), &deposit_for_msg, &[]).unwrap();
+    let deposit_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(2000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    router.execute_contract(vault.clone(), generic_addr.clone(), &deposit_msg, &[]).unwrap();
+
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1000), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1500), term: None, on_behalf_of: None };
+    router.execute_contract(vault.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+
+    /*
+     * the vault repays the borrower's loan, the overpayment comes back to the vault
+     */
+    let payoff_for_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(1200), 
+        msg: to_binary(&Cw20HookMsg::PayoffFor { borrower: borrower.to_string(), loan_id: None, policy: None }).unwrap()
+    };
+    router.execute_contract(vault.clone(), lending_addr.clone(), &payoff_for_msg, &[]).unwrap();
+
+    let res: UserInfoResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::GetUserInfo { address: borrower.to_string() }
+    ).unwrap();
+    assert_eq!(res.deposits, vec![CollateralDeposit { token: generic_addr.to_string(), amount: Uint128::new(2000) }]);
+    assert_eq!(res.deposit_receipts, res.deposits);
+    assert_eq!(res.total_loan_owed, Uint128::zero());
+    let res: UserInfoResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::GetUserInfo { address: vault.to_string() }
+    ).unwrap();
+    assert_eq!(res.total_loan_owed, Uint128::new(1500));
+
+    let balance = lending_token_contract.balance::<_, _, Empty>(&router, vault).unwrap();
+    assert_eq!(balance.u128(), 500);
+    let balance = lending_token_contract.balance::<_, _, Empty>(&router, borrower).unwrap();
+    assert_eq!(balance.u128(), 1000);
+}
+
+#[test]
+fn flash_loan() {
+    let admin = Addr::unchecked("admin");
+    let depositor = Addr::unchecked("depositor");
+    let bot = Addr::unchecked("bot");
+    let mut router = App::default();
+
+    let receiver_id = router.store_code(contract_mock_flash_receiver());
+    let receiver_addr = router.instantiate_contract(
+        receiver_id,
+        admin.clone(), 
+        &Empty {}, 
+        &[], 
+        "RECEIVER", 
+        None
+    ).unwrap();
+    let (generic_addr, lending_protocol_addr, _, _) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: depositor.to_string(), amount: Uint128::new(2000) },
+            Cw20Coin { address: receiver_addr.to_string(), amount: Uint128::new(40) },
+        ]);
+    let generic_token_contract = Cw20Contract(generic_addr.clone());
+
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(2000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    router.execute_contract(depositor.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+
+    /*
+     * the loan comes back with its 1% fee, which goes to the depositors
+     */
+    let flash_msg = ExecuteMsg::FlashLoan {
+        token: generic_addr.to_string(),
+        amount: Uint128::new(1000),
+        receiver: receiver_addr.to_string(),
+        msg: to_binary(&MockFlashReceiverExecuteMsg::Repay { token: generic_addr.to_string(), amount: Uint128::new(1010) }).unwrap(),
+    };
+    let res = router.execute_contract(bot.clone(), lending_protocol_addr.clone(), &flash_msg, &[]).unwrap();
+    // the receiver is told what was lent and what it owes on top
+    let callback = res.events.iter()
+        .find(|event| {
+            event.ty == "wasm"
+                && event.attributes.iter().any(|attr| attr.key == "_contract_addr" && attr.value == receiver_addr.as_str())
+        })
+        .unwrap();
+    let callback: Vec<_> = callback.attributes.iter()
+        .filter(|attr| attr.key != "_contract_addr")
+        .map(|attr| (attr.key.as_str(), attr.value.as_str()))
+        .collect();
+    assert_eq!(callback, vec![("lent", "1000"), ("fee", "10")]);
+    let balance = generic_token_contract.balance::<_, _, Empty>(&router, lending_protocol_addr.clone()).unwrap();
+    assert_eq!(balance.u128(), 2010);
+    let balance = generic_token_contract.balance::<_, _, Empty>(&router, admin.clone()).unwrap();
+    assert_eq!(balance.u128(), 0);
+    let balance = generic_token_contract.balance::<_, _, Empty>(&router, receiver_addr.clone()).unwrap();
+    assert_eq!(balance.u128(), 30);
+    let res: UserInfoResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(),
+        &QueryMsg::GetUserInfo { address: depositor.to_string() }
+    ).unwrap();
+    assert_eq!(res.deposits, vec![CollateralDeposit { token: generic_addr.to_string(), amount: Uint128::new(2010) }]);
+    assert_eq!(res.deposit_receipts, vec![CollateralDeposit { token: generic_addr.to_string(), amount: Uint128::new(2000) }]);
+
+    /*
+     * with a reserve factor the treasury takes its share of the fee
+     */
+    let update_msg = ExecuteMsg::UpdateConfig {
+        oracle: None,
+        max_price_age: None,
+        close_factor: None,
+        reserve_factor: Some(Decimal::percent(20)),
+        treasury: None,
+        term_loan_rate: None,
+        penalty_rate: None,
+        grace_period: None,
+        flash_loan_fee: None,
+    };
+    router.execute_contract(admin.clone(), lending_protocol_addr.clone(), &update_msg, &[]).unwrap();
+    router.execute_contract(bot.clone(), lending_protocol_addr.clone(), &flash_msg, &[]).unwrap();
+    let balance = generic_token_contract.balance::<_, _, Empty>(&router, lending_protocol_addr.clone()).unwrap();
+    assert_eq!(balance.u128(), 2018);
+    let balance = generic_token_contract.balance::<_, _, Empty>(&router, admin.clone()).unwrap();
+    assert_eq!(balance.u128(), 2);
+    let balance = generic_token_contract.balance::<_, _, Empty>(&router, receiver_addr.clone()).unwrap();
+    assert_eq!(balance.u128(), 20);
+
+    /*
+     * a loan repaid without its fee is reverted
+     */
+    let flash_msg = ExecuteMsg::FlashLoan {
+        token: generic_addr.to_string(),
+        amount: Uint128::new(1000),
+        receiver: receiver_addr.to_string(),
+        msg: to_binary(&MockFlashReceiverExecuteMsg::Repay { token: generic_addr.to_string(), amount: Uint128::new(1005) }).unwrap(),
+    };
+    router.execute_contract(bot.clone(), lending_protocol_addr.clone(), &flash_msg, &[]).unwrap_err();
+    let balance = generic_token_contract.balance::<_, _, Empty>(&router, lending_protocol_addr.clone()).unwrap();
+    assert_eq!(balance.u128(), 2018);
+    let balance = generic_token_contract.balance::<_, _, Empty>(&router, receiver_addr.clone()).unwrap();
+    assert_eq!(balance.u128(), 20);
+
+    /*
+     * no more than the deposits can be lent
+     */
+    let flash_msg = ExecuteMsg::FlashLoan {
+        token: generic_addr.to_string(),
+        amount: Uint128::new(2019),
+        receiver: receiver_addr.to_string(),
+        msg: to_binary(&MockFlashReceiverExecuteMsg::Repay { token: generic_addr.to_string(), amount: Uint128::new(2100) }).unwrap(),
+    };
+    router.execute_contract(bot, lending_protocol_addr.clone(), &flash_msg, &[]).unwrap_err();
+
+    // the depositor withdraws the deposit with the fees it earned
+    let withdraw_msg = ExecuteMsg::Withdraw { token: generic_addr.to_string(), amount: Uint128::new(2018) };
+    router.execute_contract(depositor.clone(), lending_protocol_addr.clone(), &withdraw_msg, &[]).unwrap();
+    let balance = generic_token_contract.balance::<_, _, Empty>(&router, depositor).unwrap();
+    assert_eq!(balance.u128(), 2018);
+    let balance = generic_token_contract.balance::<_, _, Empty>(&router, lending_protocol_addr).unwrap();
+    assert_eq!(balance.u128(), 0);
+}
+
+#[test]
+fn admin_roles() {
+    let admin = Addr::unchecked("admin");
+    let new_admin = Addr::unchecked("new_admin");
+    let risk_manager = Addr::unchecked("risk_manager");
+    let treasurer = Addr::unchecked("treasurer");
+    let mut router = App::default();
+
+    let (_, lending_protocol_addr, _, _) = setup_contracts(&mut router, &admin, vec![]);
+
+    /*
+     * the admin stays in charge until the proposed admin accepts
+     */
+    let propose_msg = ExecuteMsg::ProposeAdmin { address: new_admin.to_string() };
+    router.execute_contract(new_admin.clone(), lending_protocol_addr.clone(), &propose_msg, &[]).unwrap_err();
+    router.execute_contract(admin.clone(), lending_protocol_addr.clone(), &propose_msg, &[]).unwrap();
+    let res: AdminResponse = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &QueryMsg::Admin {}).unwrap();
+    assert_eq!(res, AdminResponse { admin: Some(admin.clone()), pending_admin: Some(new_admin.clone()) });
+
+    router.execute_contract(risk_manager.clone(), lending_protocol_addr.clone(), &ExecuteMsg::AcceptAdmin {}, &[]).unwrap_err();
+    router.execute_contract(new_admin.clone(), lending_protocol_addr.clone(), &ExecuteMsg::AcceptAdmin {}, &[]).unwrap();
+    let res: AdminResponse = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &QueryMsg::Admin {}).unwrap();
+    assert_eq!(res, AdminResponse { admin: Some(new_admin.clone()), pending_admin: None });
+
+    /*
+     * operators can only do what their role allows
+     */
+    let grant_msg = ExecuteMsg::GrantRole { address: risk_manager.to_string(), role: Role::RiskManager };
+    router.execute_contract(admin.clone(), lending_protocol_addr.clone(), &grant_msg, &[]).unwrap_err();
+    router.execute_contract(new_admin.clone(), lending_protocol_addr.clone(), &grant_msg, &[]).unwrap();
+    let grant_msg = ExecuteMsg::GrantRole { address: treasurer.to_string(), role: Role::Treasurer };
+    router.execute_contract(new_admin.clone(), lending_protocol_addr.clone(), &grant_msg, &[]).unwrap();
+    let res: RolesResponse = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &QueryMsg::Roles {}).unwrap();
+    assert_eq!(res.roles, vec![
+        RoleInfo { address: risk_manager.clone(), role: Role::RiskManager },
+        RoleInfo { address: treasurer.clone(), role: Role::Treasurer },
+    ]);
+
+    let model_msg = ExecuteMsg::UpdateInterestRateModel {
+        model: InterestRateModelConfig::Fixed(FixedRateModel { rate: Decimal::percent(4) }),
+    };
+    router.execute_contract(treasurer.clone(), lending_protocol_addr.clone(), &model_msg, &[]).unwrap_err();
+    router.execute_contract(risk_manager.clone(), lending_protocol_addr.clone(), &model_msg, &[]).unwrap();
+
+    let withdraw_msg = ExecuteMsg::WithdrawReserves { amount: Uint128::new(1), recipient: None };
+    let err = router.execute_contract(risk_manager.clone(), lending_protocol_addr.clone(), &withdraw_msg, &[]).unwrap_err();
+    assert_eq!(err.root_cause().to_string(), "Unauthorized");
+    // the treasurer gets past the role check, there are just no reserves yet
+    let err = router.execute_contract(treasurer.clone(), lending_protocol_addr.clone(), &withdraw_msg, &[]).unwrap_err();
+    assert_eq!(err.root_cause().to_string(), "Insufficient Funds");
+
+    let revoke_msg = ExecuteMsg::RevokeRole { address: risk_manager.to_string() };
+    router.execute_contract(new_admin.clone(), lending_protocol_addr.clone(), &revoke_msg, &[]).unwrap();
+    router.execute_contract(risk_manager, lending_protocol_addr.clone(), &model_msg, &[]).unwrap_err();
+
+    /*
+     * once renounced nobody can act as admin
+     */
+    router.execute_contract(new_admin.clone(), lending_protocol_addr.clone(), &ExecuteMsg::RenounceAdmin {}, &[]).unwrap();
+    let res: AdminResponse = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &QueryMsg::Admin {}).unwrap();
+    assert_eq!(res, AdminResponse { admin: None, pending_admin: None });
+    router.execute_contract(new_admin, lending_protocol_addr, &model_msg, &[]).unwrap_err();
+}
+
+#[test]
+fn pause() {
+    let admin = Addr::unchecked("admin");
+    let guardian = Addr::unchecked("guardian");
+    let borrower = Addr::unchecked("borrower");
+    let saver = Addr::unchecked("saver");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, lending_addr, _) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: borrower.to_string(), amount: Uint128::new(2000) },
+            Cw20Coin { address: saver.to_string(), amount: Uint128::new(2000) },
+        ]);
+    let grant_msg = ExecuteMsg::GrantRole { address: guardian.to_string(), role: Role::PauseGuardian };
+    router.execute_contract(admin.clone(), lending_protocol_addr.clone(), &grant_msg, &[]).unwrap();
+
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(1000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    router.execute_contract(borrower.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    router.execute_contract(saver.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(500), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+
+    /*
+     * only the guardian or the admin can pause
+     */
+    let pause_msg = ExecuteMsg::SetPaused { deposit: Some(true), borrow: Some(true), withdraw: None, payoff: None, liquidate: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &pause_msg, &[]).unwrap_err();
+    router.execute_contract(guardian.clone(), lending_protocol_addr.clone(), &pause_msg, &[]).unwrap();
+    let res: PauseState = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &QueryMsg::PauseStatus {}).unwrap();
+    assert_eq!(res, PauseState { deposit: true, borrow: true, withdraw: false, payoff: false, liquidate: false });
+
+    /*
+     * with borrowing paused, debt-free withdrawals and repayments stay open
+     */
+    let err = router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap_err();
+    assert_eq!(err.root_cause().to_string(), "borrow is paused");
+    let err = router.execute_contract(saver.clone(), generic_addr.clone(), &send_msg, &[]).unwrap_err();
+    assert_eq!(err.root_cause().to_string(), "deposit is paused");
+    let withdraw_msg = ExecuteMsg::Withdraw { token: generic_addr.to_string(), amount: Uint128::new(100) };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &withdraw_msg, &[]).unwrap_err();
+    router.execute_contract(saver.clone(), lending_protocol_addr.clone(), &withdraw_msg, &[]).unwrap();
+
+    let payoff_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(500), 
+        msg: to_binary(&Cw20HookMsg::Payoff { loan_id: None, policy: None }).unwrap()
+    };
+    router.execute_contract(borrower.clone(), lending_addr.clone(), &payoff_msg, &[]).unwrap();
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &withdraw_msg, &[]).unwrap();
+
+    /*
+     * resuming borrows reopens them
+     */
+    let resume_msg = ExecuteMsg::SetPaused { deposit: None, borrow: Some(false), withdraw: None, payoff: None, liquidate: None };
+    router.execute_contract(admin, lending_protocol_addr.clone(), &resume_msg, &[]).unwrap();
+    router.execute_contract(borrower, lending_protocol_addr, &borrow_msg, &[]).unwrap();
+}
+
+#[test]
+fn caps() {
+    let admin = Addr::unchecked("admin");
+    let borrower = Addr::unchecked("borrower");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, _, _) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: borrower.to_string(), amount: Uint128::new(3000) },
+        ]);
+
+    let list_msg = ExecuteMsg::ListMarket {
+        token: generic_addr.to_string(),
+        native: false,
+        collateral_factor: Decimal::one(),
+        liquidation_threshold: Decimal::one(),
+        liquidation_bonus: Decimal::percent(5),
+        supply_cap: Some(Uint128::new(2500)),
+    };
+    router.execute_contract(admin.clone(), lending_protocol_addr.clone(), &list_msg, &[]).unwrap();
+    let cap_msg = ExecuteMsg::SetBorrowCap { borrow_cap: Some(Uint128::new(800)) };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &cap_msg, &[]).unwrap_err();
+    router.execute_contract(admin, lending_protocol_addr.clone(), &cap_msg, &[]).unwrap();
+
+    /*
+     * deposits stop at the supply cap
+     */
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(2000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    router.execute_contract(borrower.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(1000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    let err = router.execute_contract(borrower.clone(), generic_addr.clone(), &send_msg, &[]).unwrap_err();
+    assert_eq!(err.root_cause().to_string(), format!("Supply cap of 2500 reached for {}, 500 left", generic_addr));
+
+    /*
+     * borrows stop at the borrow cap even with headroom left
+     */
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1000), term: None, on_behalf_of: None };
+    let err = router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap_err();
+    assert_eq!(err.root_cause().to_string(), "Borrow cap of 800 reached, 800 left");
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(600), term: None, on_behalf_of: None };
+    router.execute_contract(borrower, lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+
+    let res: CapacityResponse = router.wrap().query_wasm_smart(lending_protocol_addr, &QueryMsg::Capacity {}).unwrap();
+    assert_eq!(res, CapacityResponse {
+        borrow_cap: Some(Uint128::new(800)),
+        total_borrows: Uint128::new(600),
+        remaining_borrows: Some(Uint128::new(200)),
+        markets: vec![MarketCapacity {
+            token: generic_addr.to_string(),
+            supply_cap: Some(Uint128::new(2500)),
+            total_deposits: Uint128::new(2000),
+            remaining: Some(Uint128::new(500)),
+        }],
+    });
+}
+
+#[test]
+fn account_liquidity() {
+    let admin = Addr::unchecked("admin");
+    let borrower = Addr::unchecked("borrower");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, _, _) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: borrower.to_string(), amount: Uint128::new(2000) },
+        ]);
+    let list_msg = ExecuteMsg::ListMarket {
+        token: generic_addr.to_string(),
+        native: false,
+        collateral_factor: Decimal::percent(50),
+        liquidation_threshold: Decimal::percent(80),
+        liquidation_bonus: Decimal::percent(5),
+        supply_cap: None,
+    };
+    router.execute_contract(admin, lending_protocol_addr.clone(), &list_msg, &[]).unwrap();
+
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(2000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    router.execute_contract(borrower.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    let res: AccountLiquidityResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::AccountLiquidity { address: borrower.to_string() }
+    ).unwrap();
+    assert_eq!(res.health_factor, None);
+    assert_eq!(res.max_borrow, Uint128::new(1000));
+    assert_eq!(res.max_withdraw, vec![CollateralDeposit { token: generic_addr.to_string(), amount: Uint128::new(2000) }]);
+
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(600), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let res: AccountLiquidityResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::AccountLiquidity { address: borrower.to_string() }
+    ).unwrap();
+    assert_eq!(res, AccountLiquidityResponse {
+        collateral_value: Uint128::new(2000),
+        debt_value: Uint128::new(600),
+        health_factor: Some(Decimal::from_ratio(1600_u128, 600_u128)),
+        max_borrow: Uint128::new(400),
+        max_withdraw: vec![CollateralDeposit { token: generic_addr.to_string(), amount: Uint128::new(800) }],
+    });
+
+    /*
+     * the reported max withdrawal is exactly what the contract allows
+     */
+    let withdraw_msg = ExecuteMsg::Withdraw { token: generic_addr.to_string(), amount: Uint128::new(801) };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &withdraw_msg, &[]).unwrap_err();
+    let withdraw_msg = ExecuteMsg::Withdraw { token: generic_addr.to_string(), amount: Uint128::new(800) };
+    router.execute_contract(borrower, lending_protocol_addr, &withdraw_msg, &[]).unwrap();
+}
+
+#[test]
+fn enumeration() {
+    let admin = Addr::unchecked("admin");
+    let alice = Addr::unchecked("alice");
+    let bob = Addr::unchecked("bob");
+    let carol = Addr::unchecked("carol");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, _, oracle_addr) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: alice.to_string(), amount: Uint128::new(4000) },
+            Cw20Coin { address: bob.to_string(), amount: Uint128::new(1000) },
+            Cw20Coin { address: carol.to_string(), amount: Uint128::new(1000) },
+        ]);
+    for user in [&carol, &alice, &bob] {
+        let send_msg = Cw20ExecuteMsg::Send { 
+            contract: lending_protocol_addr.to_string(), 
+            amount: Uint128::new(1000), 
+            msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+        };
+        router.execute_contract(user.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    }
+
+    /*
+     * users are listed in address order, a page at a time
+     */
+    let res: AllUsersResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::AllUsers { start_after: None, limit: Some(2) }
+    ).unwrap();
+    assert_eq!(res.users, vec![alice.to_string(), bob.to_string()]);
+    let res: AllUsersResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::AllUsers { start_after: Some(bob.to_string()), limit: Some(2) }
+    ).unwrap();
+    assert_eq!(res.users, vec![carol.to_string()]);
+
+    /*
+     * loans are listed with what they owe now
+     */
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(300), term: None, on_behalf_of: None };
+    router.execute_contract(alice.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(200), term: Some(86400 * 365 * 2), on_behalf_of: None };
+    router.execute_contract(alice.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(100), term: None, on_behalf_of: None };
+    router.execute_contract(alice.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    router.update_block(|block| block.time = block.time.plus_seconds(86400 * 365));
+    set_price(&mut router, &admin, &oracle_addr, &generic_addr, Decimal::one());
+
+    let res: LoansResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::Loans { address: alice.to_string(), start_after: None, limit: Some(2) }
+    ).unwrap();
+    let owed: Vec<_> = res.loans.iter().map(|loan| (loan.loan_id.u128(), loan.amount_owed.u128())).collect();
+    // 3% variable rate, 5% on the term loan
+    assert_eq!(owed, vec![(0, 309), (1, 210)]);
+    assert_eq!(res.loans[1].loan.interest, Uint128::new(10));
+    let res: LoansResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr, 
+        &QueryMsg::Loans { address: alice.to_string(), start_after: Some(Uint128::new(1)), limit: None }
+    ).unwrap();
+    let owed: Vec<_> = res.loans.iter().map(|loan| (loan.loan_id.u128(), loan.amount_owed.u128())).collect();
+    assert_eq!(owed, vec![(2, 103)]);
+}
+
+#[test]
+fn config_and_market_state() {
+    let admin = Addr::unchecked("admin");
+    let borrower = Addr::unchecked("borrower");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, lending_addr, _) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: borrower.to_string(), amount: Uint128::new(2000) },
+        ]);
+    setup_receipt_token(&mut router, &admin, &lending_protocol_addr);
+
+    let res: ConfigResponse = router.wrap().query_wasm_smart(lending_protocol_addr.clone(), &QueryMsg::Config {}).unwrap();
+    assert_eq!(res.config.lending_token, Some(lending_addr.clone()));
+    assert_eq!(res.config.admin, Some(admin));
+    assert_eq!(res.contract_version.contract, "crates.io:lending-app");
+    assert_eq!(res.contract_version.version, env!("CARGO_PKG_VERSION"));
+
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(2000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    router.execute_contract(borrower.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1000), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let supply_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(500), 
+        msg: to_binary(&Cw20HookMsg::Supply {}).unwrap()
+    };
+    router.execute_contract(borrower.clone(), lending_addr, &supply_msg, &[]).unwrap();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(500), term: None, on_behalf_of: None };
+    router.execute_contract(borrower, lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+
+    /*
+     * three quarters of the collateral value is borrowed at 3%, a third of it out of the supply
+     */
+    let res: MarketStateResponse = router.wrap().query_wasm_smart(lending_protocol_addr, &QueryMsg::MarketState {}).unwrap();
+    assert_eq!(res, MarketStateResponse {
+        total_deposits: vec![CollateralDeposit { token: generic_addr.to_string(), amount: Uint128::new(2000) }],
+        total_collateral_value: Uint128::new(2000),
+        total_borrows: Uint128::new(1500),
+        total_supply: Uint128::new(500),
+        total_reserves: Uint128::zero(),
+        utilization: Decimal::percent(75),
+        borrow_rate: Decimal::percent(3),
+        supply_rate: Decimal::percent(3),
+    });
+}
+
+#[test]
+fn simulations() {
+    let admin = Addr::unchecked("admin");
+    let borrower = Addr::unchecked("borrower");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, _, _) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: borrower.to_string(), amount: Uint128::new(2000) },
+        ]);
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(2000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    router.execute_contract(borrower.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    for _ in 0..2 {
+        let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(500), term: None, on_behalf_of: None };
+        router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    }
+
+    /*
+     * previews fail the way the operation would, or show the resulting position
+     */
+    let err = router.wrap().query_wasm_smart::<AccountLiquidityResponse>(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::SimulateBorrow { address: borrower.to_string(), amount: Uint128::new(1001), term: None }
+    ).unwrap_err();
+    assert!(err.to_string().contains("Insufficient Funds"));
+    let res: AccountLiquidityResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::SimulateBorrow { address: borrower.to_string(), amount: Uint128::new(1000), term: None }
+    ).unwrap();
+    assert_eq!(res.debt_value, Uint128::new(2000));
+    assert_eq!(res.max_borrow, Uint128::zero());
+
+    router.wrap().query_wasm_smart::<AccountLiquidityResponse>(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::SimulateWithdraw { address: borrower.to_string(), token: generic_addr.to_string(), amount: Uint128::new(1001) }
+    ).unwrap_err();
+    let res: AccountLiquidityResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::SimulateWithdraw { address: borrower.to_string(), token: generic_addr.to_string(), amount: Uint128::new(1000) }
+    ).unwrap();
+    assert_eq!(res.collateral_value, Uint128::new(1000));
+
+    let res: SimulatePayoffResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::SimulatePayoff {
+            address: borrower.to_string(),
+            amount: Uint128::new(1200),
+            loan_id: None,
+            policy: Some(RepaymentPolicy::NewestFirst),
+        }
+    ).unwrap();
+    assert_eq!(res.allocations, vec![
+        LoanPayment { loan_id: Uint128::new(1), amount: Uint128::new(500) },
+        LoanPayment { loan_id: Uint128::new(0), amount: Uint128::new(500) },
+    ]);
+    assert_eq!(res.refund_amount, Uint128::new(200));
+    assert_eq!(res.position.debt_value, Uint128::zero());
+
+    // nothing was changed by the previews
+    let res: AccountLiquidityResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr, 
+        &QueryMsg::AccountLiquidity { address: borrower.to_string() }
+    ).unwrap();
+    assert_eq!(res.collateral_value, Uint128::new(2000));
+    assert_eq!(res.debt_value, Uint128::new(1000));
+}
+
+#[test]
+fn projected_debt() {
+    let admin = Addr::unchecked("admin");
+    let borrower = Addr::unchecked("borrower");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, _, oracle_addr) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: borrower.to_string(), amount: Uint128::new(4000) },
+        ]);
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(4000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    router.execute_contract(borrower.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1000), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1000), term: Some(86400 * 30), on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+
+    /*
+     * a year out the term loan is past maturity and grace period, so it
+     * accrues the penalty rate for the last 328 days
+     */
+    let at_time = router.block_info().time.plus_seconds(86400 * 365).seconds();
+    router.wrap().query_wasm_smart::<ProjectedDebtResponse>(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::ProjectedDebt { address: borrower.to_string(), at_time: at_time - 86400 * 366 }
+    ).unwrap_err();
+    let res: ProjectedDebtResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::ProjectedDebt { address: borrower.to_string(), at_time }
+    ).unwrap();
+    assert_eq!(res.loans, vec![
+        ProjectedLoan { loan_id: Uint128::new(0), amount_owed: Uint128::new(1030), rate: Decimal::percent(3) },
+        ProjectedLoan { loan_id: Uint128::new(1), amount_owed: Uint128::new(1229), rate: Decimal::percent(25) },
+    ]);
+    assert_eq!(res.total_owed, Uint128::new(2259));
+    // weighted by the year-out balances, (1030 * 3% + 1229 * 25%) / 2259
+    assert_eq!(res.apr, Decimal::from_ratio(33815_u128, 225900_u128));
+
+    // the projection is what the contract charges once the time comes
+    router.update_block(|block| block.time = block.time.plus_seconds(86400 * 365));
+    set_price(&mut router, &admin, &oracle_addr, &generic_addr, Decimal::one());
+    let res: AccountLiquidityResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr, 
+        &QueryMsg::AccountLiquidity { address: borrower.to_string() }
+    ).unwrap();
+    assert_eq!(res.debt_value, Uint128::new(2259));
+}
+
+#[test]
+fn borrow_delegation() {
+    let admin = Addr::unchecked("admin");
+    let owner = Addr::unchecked("owner");
+    let desk = Addr::unchecked("desk");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, lending_addr, _) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: owner.to_string(), amount: Uint128::new(2000) },
+        ]);
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(2000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    router.execute_contract(owner.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+
+    // no delegation yet
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(100), term: None, on_behalf_of: Some(owner.to_string()) };
+    let err = router.execute_contract(desk.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap_err();
+    assert_eq!(err.root_cause().to_string(), "Unauthorized");
+
+    let approve_msg = ExecuteMsg::ApproveBorrowDelegate { delegate: owner.to_string(), limit: Uint128::new(1000), expires: None };
+    let err = router.execute_contract(owner.clone(), lending_protocol_addr.clone(), &approve_msg, &[]).unwrap_err();
+    assert_eq!(err.root_cause().to_string(), "Cannot delegate borrowing to yourself");
+    let expires = Expiration::AtTime(router.block_info().time.plus_seconds(86400));
+    let approve_msg = ExecuteMsg::ApproveBorrowDelegate { delegate: desk.to_string(), limit: Uint128::new(1000), expires: Some(expires) };
+    router.execute_contract(owner.clone(), lending_protocol_addr.clone(), &approve_msg, &[]).unwrap();
+
+    /*
+     * the desk receives the lending tokens and the owner takes the debt,
+     * within what is left of the delegation
+     */
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(600), term: None, on_behalf_of: Some(owner.to_string()) };
+    router.execute_contract(desk.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let balance = Cw20Contract(lending_addr.clone()).balance::<_, _, Empty>(&router, desk.clone()).unwrap();
+    assert_eq!(balance, Uint128::new(600));
+    let res: AccountLiquidityResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::AccountLiquidity { address: owner.to_string() }
+    ).unwrap();
+    assert_eq!(res.debt_value, Uint128::new(600));
+    let res: BorrowDelegationsResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::BorrowDelegations { owner: owner.to_string(), start_after: None, limit: None }
+    ).unwrap();
+    assert_eq!(res.delegations, vec![
+        BorrowDelegationInfo { delegate: desk.to_string(), limit: Uint128::new(400), expires },
+    ]);
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(401), term: None, on_behalf_of: Some(owner.to_string()) };
+    let err = router.execute_contract(desk.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap_err();
+    assert_eq!(err.root_cause().to_string(), "Borrow delegation exceeded, 400 left");
+
+    // the delegation lapses at expiry
+    router.update_block(|block| block.time = block.time.plus_seconds(86400));
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(100), term: None, on_behalf_of: Some(owner.to_string()) };
+    let err = router.execute_contract(desk.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap_err();
+    assert_eq!(err.root_cause().to_string(), "Borrow delegation has expired");
+
+    // a zero limit revokes it
+    let approve_msg = ExecuteMsg::ApproveBorrowDelegate { delegate: desk.to_string(), limit: Uint128::zero(), expires: None };
+    router.execute_contract(owner.clone(), lending_protocol_addr.clone(), &approve_msg, &[]).unwrap();
+    let res: BorrowDelegationsResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr, 
+        &QueryMsg::BorrowDelegations { owner: owner.to_string(), start_after: None, limit: None }
+    ).unwrap();
+    assert!(res.delegations.is_empty());
+}
+
+#[test]
+fn supply_only_deposits() {
+    let admin = Addr::unchecked("admin");
+    let borrower = Addr::unchecked("borrower");
+    let liquidator = Addr::unchecked("liquidator");
+    let mut router = App::new(|router, _, storage| {
+        router
+            .bank
+            .init_balance(storage, &borrower, vec![coin(1000, "uatom")])
+            .unwrap();
+    });
+
+    let (generic_addr, lending_protocol_addr, _, oracle_addr) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: borrower.to_string(), amount: Uint128::new(1000) },
+        ]);
+    let list_msg = ExecuteMsg::ListMarket {
+        token: "uatom".to_string(),
+        native: true,
+        collateral_factor: Decimal::percent(50),
+        liquidation_threshold: Decimal::percent(60),
+        liquidation_bonus: Decimal::percent(5),
+        supply_cap: None,
+    };
+    router.execute_contract(admin.clone(), lending_protocol_addr.clone(), &list_msg, &[]).unwrap();
+    router.execute_contract(admin.clone(), oracle_addr.clone(), &MockOracleExecuteMsg::SetPrice {
+        asset: "uatom".to_string(),
+        price: Decimal::one(),
+    }, &[]).unwrap();
+
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(1000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    router.execute_contract(borrower.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    let deposit_msg = ExecuteMsg::DepositNative {};
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &deposit_msg, &coins(1000, "uatom")).unwrap();
+
+    /*
+     * supply only deposits do not back borrowing
+     */
+    let collateral_msg = ExecuteMsg::SetCollateral { token: "uatom".to_string(), enabled: false };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &collateral_msg, &[]).unwrap();
+    let res: Option<UserInfoResponse> = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::GetUserInfo { address: borrower.to_string() }
+    ).unwrap();
+    let res = res.unwrap();
+    assert_eq!(res.collateral_value, Uint128::new(1000));
+    assert_eq!(res.supply_only, vec!["uatom".to_string()]);
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1001), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap_err();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(900), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+
+    // and can be withdrawn in full while the loan is open
+    let res: AccountLiquidityResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::AccountLiquidity { address: borrower.to_string() }
+    ).unwrap();
+    assert!(res.max_withdraw.contains(&CollateralDeposit { token: "uatom".to_string(), amount: Uint128::new(1000) }));
+
+    /*
+     * collateral can only be disabled while the rest covers the debt
+     */
+    let collateral_msg = ExecuteMsg::SetCollateral { token: "uatom".to_string(), enabled: true };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &collateral_msg, &[]).unwrap();
+    let collateral_msg = ExecuteMsg::SetCollateral { token: generic_addr.to_string(), enabled: false };
+    let err = router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &collateral_msg, &[]).unwrap_err();
+    assert_eq!(err.root_cause().to_string(), "Insufficient Funds");
+    let collateral_msg = ExecuteMsg::SetCollateral { token: "uatom".to_string(), enabled: false };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &collateral_msg, &[]).unwrap();
+
+    // supply only deposits cannot be seized once the position is unhealthy
+    set_price(&mut router, &admin, &oracle_addr, &generic_addr, Decimal::percent(80));
+    let liquidate_msg = ExecuteMsg::Liquidate { borrower: borrower.to_string(), collateral: "uatom".to_string(), amount: Uint128::new(100) };
+    let err = router.execute_contract(liquidator, lending_protocol_addr, &liquidate_msg, &[]).unwrap_err();
+    assert_eq!(err.root_cause().to_string(), "uatom is not enabled as collateral");
+}
+
+#[test]
+fn protocol_events() {
+    let admin = Addr::unchecked("admin");
+    let borrower = Addr::unchecked("borrower");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, lending_addr, _) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: borrower.to_string(), amount: Uint128::new(2000) },
+        ]);
+    let generic = generic_addr.to_string();
+    let user = borrower.to_string();
+
+    let send_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(2000), 
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+    };
+    let res = router.execute_contract(borrower.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    assert_eq!(lending_events(&res, events::DEPOSIT), vec![
+        attrs(&[("user", &user), ("token", &generic), ("amount", "2000"), ("deposited", "2000")]),
+    ]);
+
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1000), term: None, on_behalf_of: None };
+    router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(500), term: Some(86400 * 30), on_behalf_of: None };
+    let res = router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    assert_eq!(lending_events(&res, events::BORROW), vec![
+        attrs(&[("user", &user), ("recipient", &user), ("loan_id", "1"), ("amount", "500"), ("debt", "1500")]),
+    ]);
+
+    /*
+     * a payment spread over several loans gives one event per loan
+     */
+    let payoff_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(1200), 
+        msg: to_binary(&Cw20HookMsg::Payoff { loan_id: None, policy: None }).unwrap()
+    };
+    let res = router.execute_contract(borrower.clone(), lending_addr, &payoff_msg, &[]).unwrap();
+    assert_eq!(lending_events(&res, events::REPAY), vec![
+        attrs(&[("user", &user), ("payer", &user), ("loan_id", "0"), ("amount", "1000"), ("debt", "300")]),
+        attrs(&[("user", &user), ("payer", &user), ("loan_id", "1"), ("amount", "200"), ("debt", "300")]),
+    ]);
+
+    let withdraw_msg = ExecuteMsg::Withdraw { token: generic.clone(), amount: Uint128::new(100) };
+    let res = router.execute_contract(borrower.clone(), lending_protocol_addr.clone(), &withdraw_msg, &[]).unwrap();
+    assert_eq!(lending_events(&res, events::WITHDRAW), vec![
+        attrs(&[("user", &user), ("recipient", &user), ("token", &generic), ("amount", "100"), ("deposited", "1900"), ("debt", "300")]),
+    ]);
+
+    let cap_msg = ExecuteMsg::SetBorrowCap { borrow_cap: Some(Uint128::new(5000)) };
+    let res = router.execute_contract(admin, lending_protocol_addr, &cap_msg, &[]).unwrap();
+    assert_eq!(lending_events(&res, events::CONFIG_UPDATE), vec![
+        attrs(&[("action", "set_borrow_cap"), ("borrow_cap", "5000")]),
+    ]);
+}
+
+#[test]
+fn borrow_index() {
+    let admin = Addr::unchecked("admin");
+    let alice = Addr::unchecked("alice");
+    let bob = Addr::unchecked("bob");
+    let mut router = App::default();
+
+    let (generic_addr, lending_protocol_addr, lending_addr, oracle_addr) =
+        setup_contracts(&mut router, &admin, vec![
+            Cw20Coin { address: alice.to_string(), amount: Uint128::new(2000) },
+            Cw20Coin { address: bob.to_string(), amount: Uint128::new(2000) },
+        ]);
+    for user in [&alice, &bob] {
+        let send_msg = Cw20ExecuteMsg::Send { 
+            contract: lending_protocol_addr.to_string(), 
+            amount: Uint128::new(2000), 
+            msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap()
+        };
+        router.execute_contract(user.clone(), generic_addr.clone(), &send_msg, &[]).unwrap();
+    }
+
+    /*
+     * at 3% a year the index is 1.015 after six months and 1.030225 after a
+     * year, the second loan is scaled by the index it was taken out at
+     */
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1000), term: None, on_behalf_of: None };
+    router.execute_contract(alice.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    router.update_block(|block| block.time = block.time.plus_seconds(86400 * 365 / 2));
+    set_price(&mut router, &admin, &oracle_addr, &generic_addr, Decimal::one());
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(500), term: None, on_behalf_of: None };
+    router.execute_contract(alice.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    let borrow_msg = ExecuteMsg::Borrow { amount: Uint128::new(1000), term: None, on_behalf_of: None };
+    router.execute_contract(bob.clone(), lending_protocol_addr.clone(), &borrow_msg, &[]).unwrap();
+    router.update_block(|block| block.time = block.time.plus_seconds(86400 * 365 / 2));
+    set_price(&mut router, &admin, &oracle_addr, &generic_addr, Decimal::one());
+
+    let res: LoansResponse = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::Loans { address: alice.to_string(), start_after: None, limit: None }
+    ).unwrap();
+    let owed: Vec<_> = res.loans.iter().map(|loan| loan.amount_owed).collect();
+    assert_eq!(owed, vec![Uint128::new(1030), Uint128::new(507)]);
+    let user_info: Option<UserInfoResponse> = router.wrap().query_wasm_smart(
+        lending_protocol_addr.clone(), 
+        &QueryMsg::GetUserInfo { address: alice.to_string() }
+    ).unwrap();
+    assert_eq!(user_info.unwrap().total_loan_owed, Uint128::new(1538));
+
+    /*
+     * after a partial payoff the market's scaled debt is still the sum of
+     * the users' scaled debt
+     */
+    let payoff_msg = Cw20ExecuteMsg::Send { 
+        contract: lending_protocol_addr.to_string(), 
+        amount: Uint128::new(600), 
+        msg: to_binary(&Cw20HookMsg::Payoff { loan_id: None, policy: None }).unwrap()
+    };
+    router.execute_contract(alice.clone(), lending_addr, &payoff_msg, &[]).unwrap();
+    let state: MarketState = router.wrap()
+        .query_wasm_raw(lending_protocol_addr.clone(), MARKET_STATE.as_slice())
+        .map(|raw| from_slice(&raw.unwrap()).unwrap())
+        .unwrap();
+    let scaled_debt: Uint128 = [&alice, &bob].iter()
+        .map(|user| {
+            let raw = router.wrap().query_wasm_raw(lending_protocol_addr.clone(), USER_INFO.key(user).to_vec()).unwrap();
+            from_slice::<UserData>(&raw.unwrap()).unwrap().scaled_debt
+        })
+        .sum();
+    assert_eq!(state.total_scaled_debt, scaled_debt);
+    let user_info: Option<UserInfoResponse> = router.wrap().query_wasm_smart(
+        lending_protocol_addr, 
+        &QueryMsg::GetUserInfo { address: alice.to_string() }
+    ).unwrap();
+    assert_eq!(user_info.unwrap().total_loan_owed, Uint128::new(938));
+}
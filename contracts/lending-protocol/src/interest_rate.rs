@@ -0,0 +1,123 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::Decimal;
+
+use crate::error::ContractError;
+
+/// Yearly borrow rate as a function of pool utilization
+pub trait InterestRateModel {
+    fn borrow_rate(&self, utilization: Decimal) -> Decimal;
+
+    fn validate(&self) -> Result<(), ContractError> {
+        Ok(())
+    }
+}
+
+/// Same rate regardless of utilization
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FixedRateModel {
+    pub rate: Decimal,
+}
+
+impl InterestRateModel for FixedRateModel {
+    fn borrow_rate(&self, _utilization: Decimal) -> Decimal {
+        self.rate
+    }
+}
+
+/// `base_rate + slope * utilization`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LinearRateModel {
+    pub base_rate: Decimal,
+    pub slope: Decimal,
+}
+
+impl InterestRateModel for LinearRateModel {
+    fn borrow_rate(&self, utilization: Decimal) -> Decimal {
+        self.base_rate + self.slope * utilization
+    }
+}
+
+/// Linear up to the `kink` utilization, after which the rate grows with the
+/// steeper `jump_multiplier` to push utilization back down
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct JumpRateModel {
+    pub base_rate: Decimal,
+    pub multiplier: Decimal,
+    pub jump_multiplier: Decimal,
+    pub kink: Decimal,
+}
+
+impl InterestRateModel for JumpRateModel {
+    fn borrow_rate(&self, utilization: Decimal) -> Decimal {
+        if utilization <= self.kink {
+            return self.base_rate + self.multiplier * utilization;
+        }
+        self.base_rate + self.multiplier * self.kink + self.jump_multiplier * (utilization - self.kink)
+    }
+
+    fn validate(&self) -> Result<(), ContractError> {
+        if self.kink > Decimal::one() {
+            return Err(ContractError::InvalidConfig { reason: "kink must not exceed 1".to_string() });
+        }
+        Ok(())
+    }
+}
+
+/// Interest rate model stored in contract state, selectable by the admin
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum InterestRateModelConfig {
+    Fixed(FixedRateModel),
+    Linear(LinearRateModel),
+    JumpRate(JumpRateModel),
+}
+
+impl InterestRateModelConfig {
+    fn model(&self) -> &dyn InterestRateModel {
+        match self {
+            InterestRateModelConfig::Fixed(model) => model,
+            InterestRateModelConfig::Linear(model) => model,
+            InterestRateModelConfig::JumpRate(model) => model,
+        }
+    }
+}
+
+impl InterestRateModel for InterestRateModelConfig {
+    fn borrow_rate(&self, utilization: Decimal) -> Decimal {
+        self.model().borrow_rate(utilization)
+    }
+
+    fn validate(&self) -> Result<(), ContractError> {
+        self.model().validate()
+    }
+}
+
+#[cfg(test)]
+mod interest_rate_tests {
+    use cosmwasm_std::Decimal;
+
+    use super::{InterestRateModel, JumpRateModel, LinearRateModel};
+
+    #[test]
+    fn linear_rate_test() {
+        let model = LinearRateModel { base_rate: Decimal::percent(2), slope: Decimal::percent(10) };
+        assert_eq!(model.borrow_rate(Decimal::zero()), Decimal::percent(2));
+        assert_eq!(model.borrow_rate(Decimal::percent(50)), Decimal::percent(7));
+    }
+
+    #[test]
+    fn jump_rate_test() {
+        let model = JumpRateModel {
+            base_rate: Decimal::percent(2),
+            multiplier: Decimal::percent(10),
+            jump_multiplier: Decimal::percent(200),
+            kink: Decimal::percent(80),
+        };
+        assert_eq!(model.borrow_rate(Decimal::percent(50)), Decimal::percent(7));
+        assert_eq!(model.borrow_rate(Decimal::percent(80)), Decimal::percent(10));
+        // 2% + 8% below the kink, then 200% of the 10% above it
+        assert_eq!(model.borrow_rate(Decimal::percent(90)), Decimal::percent(30));
+    }
+}
@@ -1,7 +1,15 @@
 pub mod contract;
+mod enumerable;
 mod error;
+pub mod events;
 mod integration_test;
+mod migrations;
+pub mod interest_rate;
+mod mock_flash_receiver;
+mod mock_oracle;
 pub mod msg;
+pub mod oracle;
+mod simulation;
 pub mod state;
 
 pub use crate::error::ContractError;
@@ -0,0 +1,319 @@
+//! Storage migrations from the layouts of earlier contract versions
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{Addr, Decimal, DepsMut, Env, Order, StdResult, Timestamp, Uint128};
+use cw_storage_plus::{Item, Map};
+
+use crate::contract::{validate_config, validate_market};
+use crate::error::ContractError;
+use crate::interest_rate::InterestRateModel;
+use crate::msg::MigrateMsg;
+use crate::oracle::query_price;
+use crate::state::{
+    CollateralState, Config, LoanInfo, MarketConfig, MarketState, PauseState, UserData, COLLATERAL_STATE, CONFIG,
+    LOANS, MARKETS, MARKET_STATE, PAUSE_STATE, RATE_MODEL, USER_INFO,
+};
+
+/// Rewrites storage from the layout of contract version `from`
+pub struct Migration {
+    pub from: &'static str,
+    pub run: fn(DepsMut, &Env, &MigrateMsg) -> Result<(), ContractError>,
+}
+
+/// Registered migration steps, oldest first. Each runs when the stored
+/// version is at or below its `from` version.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { from: "0.1.0", run: migrate_v0_1 },
+];
+
+/// Parses a `major.minor.patch` version for ordering
+pub fn parse_version(version: &str) -> Result<(u64, u64, u64), ContractError> {
+    let invalid = || ContractError::InvalidMigration { reason: format!("invalid version {}", version) };
+    let parts: Vec<u64> = version
+        .split('.')
+        .map(|part| part.parse::<u64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    match parts[..] {
+        [major, minor, patch] => Ok((major, minor, patch)),
+        _ => Err(invalid()),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ConfigV0_1 {
+    pub admin: Addr,
+    pub generic_token: Addr,
+    pub lending_token: Option<Addr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UserDataV0_1 {
+    pub generic_token_deposited: Uint128,
+    pub borrow_amt: Uint128,
+    pub curr_loan_id: Uint128,
+}
+
+/// 0.1.0 loan, amounts are stored as the atomics of a `Decimal`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LoanInfoV0_1 {
+    pub start_time: Timestamp,
+    pub last_update_time: Timestamp,
+    pub principal: Decimal,
+    pub amount_owed: Decimal,
+}
+
+impl LoanInfoV0_1 {
+    /// Amount owed at `ts` under the 0.1.0 terms: 3% a year compounded on
+    /// loan anniversaries, accrued by whole days in between
+    pub fn amount_owed_at(&self, ts: Timestamp) -> Uint128 {
+        let yearly = Decimal::one() + Decimal::percent(3);
+        let mut principal = self.principal;
+        let mut amount_owed = self.amount_owed;
+
+        let prev_days_elapsed = (self.last_update_time.seconds() - self.start_time.seconds()) / 86400;
+        let new_days_elapsed = (ts.seconds() - self.start_time.seconds()) / 86400;
+        let mut inbetween_days = new_days_elapsed - prev_days_elapsed;
+        if prev_days_elapsed / 365 != new_days_elapsed / 365 {
+            let apply_days = (prev_days_elapsed / 365 + 1) * 365 - prev_days_elapsed;
+            amount_owed = principal * yearly;
+            principal = amount_owed;
+            inbetween_days -= apply_days;
+            while inbetween_days >= 365 {
+                amount_owed = principal * yearly;
+                principal = amount_owed;
+                inbetween_days -= 365;
+            }
+        }
+        if inbetween_days > 0 {
+            amount_owed = principal * (Decimal::one() + Decimal::percent(3) * Decimal::from_ratio(inbetween_days, 365_u128));
+        }
+        amount_owed.atomics()
+    }
+}
+
+pub const CONFIG_V0_1: Item<ConfigV0_1> = Item::new("Config");
+pub const USER_INFO_V0_1: Map<&Addr, UserDataV0_1> = Map::new("User");
+pub const LOANS_V0_1: Map<(&Addr, u128), LoanInfoV0_1> = Map::new("Loan");
+
+/// 0.1.0 had a single collateral token and fixed-rate loans. The token is
+/// listed as the first market and loans carry over as variable rate loans
+/// owing what they owed at migration.
+fn migrate_v0_1(deps: DepsMut, env: &Env, msg: &MigrateMsg) -> Result<(), ContractError> {
+    let params = msg.v0_1.as_ref().ok_or_else(|| ContractError::InvalidMigration {
+        reason: "v0_1 parameters are required to migrate from 0.1.0".to_string(),
+    })?;
+    let legacy_config = CONFIG_V0_1.load(deps.storage)?;
+    let generic_token = legacy_config.generic_token;
+
+    let config = Config {
+        admin: Some(legacy_config.admin),
+        pending_admin: None,
+        lending_token: legacy_config.lending_token,
+        receipt_token: None,
+        oracle: deps.api.addr_validate(&params.oracle)?,
+        max_price_age: params.max_price_age,
+        close_factor: params.close_factor,
+        reserve_factor: params.reserve_factor,
+        treasury: deps.api.addr_validate(&params.treasury)?,
+        term_loan_rate: params.term_loan_rate,
+        penalty_rate: params.penalty_rate,
+        grace_period: params.grace_period,
+        flash_loan_fee: params.flash_loan_fee,
+        borrow_cap: None,
+    };
+    validate_config(&config)?;
+    CONFIG.save(deps.storage, &config)?;
+    let market = MarketConfig {
+        native: false,
+        collateral_factor: params.collateral_factor,
+        liquidation_threshold: params.liquidation_threshold,
+        liquidation_bonus: params.liquidation_bonus,
+        supply_cap: None,
+    };
+    validate_market(&market)?;
+    MARKETS.save(deps.storage, generic_token.as_str(), &market)?;
+    // the deposits are valued at this price for utilization until a borrower
+    // or liquidator caches a newer one
+    let last_price = query_price(&deps.querier, env, &config.oracle, generic_token.as_str(), config.max_price_age)?.price;
+    params.interest_rate_model.validate()?;
+    RATE_MODEL.save(deps.storage, &params.interest_rate_model)?;
+    PAUSE_STATE.save(deps.storage, &PauseState::default())?;
+
+    // the borrow index starts at one, so the scaled debt of a loan is what it owes
+    let mut state = MarketState::new(env.block.time);
+    let mut total_deposits = Uint128::zero();
+    let users: StdResult<Vec<_>> = USER_INFO_V0_1.range(deps.storage, None, None, Order::Ascending).collect();
+    for (addr, legacy_user) in users? {
+        let mut user_data = UserData {
+            borrow_amt: legacy_user.borrow_amt,
+            curr_loan_id: legacy_user.curr_loan_id,
+            ..UserData::new()
+        };
+        if !legacy_user.generic_token_deposited.is_zero() {
+            user_data = user_data.deposit_amount(generic_token.as_str(), legacy_user.generic_token_deposited);
+            total_deposits += legacy_user.generic_token_deposited;
+        }
+        let loans: StdResult<Vec<_>> = LOANS_V0_1.prefix(&addr).range(deps.storage, None, None, Order::Ascending).collect();
+        for (loan_id, legacy_loan) in loans? {
+            let owed = legacy_loan.amount_owed_at(env.block.time);
+            if owed.is_zero() {
+                LOANS.remove(deps.storage, (&addr, loan_id));
+                continue;
+            }
+            // what is owed beyond the legacy principal is interest, which
+            // repayments cover first
+            let principal = legacy_loan.principal.atomics().min(owed);
+            LOANS.save(deps.storage, (&addr, loan_id), &LoanInfo::new(legacy_loan.start_time, principal, owed))?;
+            user_data.scaled_debt += owed;
+            state.total_scaled_debt += owed;
+        }
+        USER_INFO.save(deps.storage, &addr, &user_data)?;
+    }
+    COLLATERAL_STATE.save(deps.storage, generic_token.as_str(), &CollateralState {
+        total_deposits,
+        total_receipts: total_deposits,
+        last_price,
+    })?;
+    MARKET_STATE.save(deps.storage, &state)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod migration_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, MockApi, MockQuerier, MockStorage};
+    use cosmwasm_std::{to_binary, Addr, ContractResult, Decimal, OwnedDeps, SystemResult, Timestamp, Uint128};
+    use cw2::{get_contract_version, set_contract_version};
+
+    use super::{ConfigV0_1, LoanInfoV0_1, UserDataV0_1, CONFIG_V0_1, LOANS_V0_1, USER_INFO_V0_1};
+    use crate::contract::{get_market_state, migrate};
+    use crate::interest_rate::{FixedRateModel, InterestRateModelConfig, LinearRateModel};
+    use crate::msg::{MigrateFromV0_1, MigrateMsg};
+    use crate::oracle::PriceResponse;
+    use crate::state::{CollateralDeposit, CONFIG, COLLATERAL_STATE, LOANS, MARKETS, MARKET_STATE, USER_INFO};
+
+    fn v0_1_params() -> MigrateFromV0_1 {
+        MigrateFromV0_1 {
+            oracle: "oracle".to_string(),
+            max_price_age: 3600,
+            collateral_factor: Decimal::percent(50),
+            liquidation_threshold: Decimal::percent(80),
+            liquidation_bonus: Decimal::percent(5),
+            close_factor: Decimal::percent(50),
+            reserve_factor: Decimal::zero(),
+            treasury: "treasury".to_string(),
+            term_loan_rate: Decimal::percent(5),
+            penalty_rate: Decimal::percent(20),
+            grace_period: 86400 * 7,
+            flash_loan_fee: Decimal::zero(),
+            interest_rate_model: InterestRateModelConfig::Fixed(FixedRateModel { rate: Decimal::percent(3) }),
+        }
+    }
+
+    /// 0.1.0 storage with a 2000 token deposit and a 1000 token loan taken
+    /// out at time zero
+    fn save_v0_1_state(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, user: &Addr) {
+        set_contract_version(deps.as_mut().storage, "crates.io:lending-app", "0.1.0").unwrap();
+        CONFIG_V0_1.save(deps.as_mut().storage, &ConfigV0_1 {
+            admin: Addr::unchecked("admin"),
+            generic_token: Addr::unchecked("generic"),
+            lending_token: Some(Addr::unchecked("lending")),
+        }).unwrap();
+        USER_INFO_V0_1.save(deps.as_mut().storage, user, &UserDataV0_1 {
+            generic_token_deposited: Uint128::new(2000),
+            borrow_amt: Uint128::new(1000),
+            curr_loan_id: Uint128::new(1),
+        }).unwrap();
+        LOANS_V0_1.save(deps.as_mut().storage, (user, 0), &LoanInfoV0_1 {
+            start_time: Timestamp::from_seconds(0),
+            last_update_time: Timestamp::from_seconds(0),
+            principal: Decimal::from_atomics(1000_u128, 18).unwrap(),
+            amount_owed: Decimal::from_atomics(1000_u128, 18).unwrap(),
+        }).unwrap();
+    }
+
+    fn mock_oracle_price(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, price: Decimal, last_updated: Timestamp) {
+        deps.querier.update_wasm(move |_| {
+            let res = PriceResponse { price, last_updated: last_updated.seconds() };
+            SystemResult::Ok(ContractResult::Ok(to_binary(&res).unwrap()))
+        });
+    }
+
+    #[test]
+    fn migrate_from_v0_1() {
+        let mut deps = mock_dependencies();
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(86400 * 365);
+        let user = Addr::unchecked("user");
+        save_v0_1_state(&mut deps, &user);
+        mock_oracle_price(&mut deps, Decimal::one(), env.block.time);
+
+        // parameters for the new layout are required
+        migrate(deps.as_mut(), env.clone(), MigrateMsg { v0_1: None }).unwrap_err();
+        migrate(deps.as_mut(), env.clone(), MigrateMsg { v0_1: Some(v0_1_params()) }).unwrap();
+        assert_eq!(get_contract_version(&deps.storage).unwrap().version, env!("CARGO_PKG_VERSION"));
+
+        let config = CONFIG.load(&deps.storage).unwrap();
+        assert_eq!(config.admin, Some(Addr::unchecked("admin")));
+        assert_eq!(config.lending_token, Some(Addr::unchecked("lending")));
+        let market = MARKETS.load(&deps.storage, "generic").unwrap();
+        assert_eq!(market.collateral_factor, Decimal::percent(50));
+        assert_eq!(COLLATERAL_STATE.load(&deps.storage, "generic").unwrap().total_deposits, Uint128::new(2000));
+
+        // a year of 3% interest accrued under the old terms carries over as
+        // interest on top of the legacy principal
+        let user_data = USER_INFO.load(&deps.storage, &user).unwrap();
+        assert_eq!(user_data.deposits, vec![CollateralDeposit { token: "generic".to_string(), amount: Uint128::new(2000) }]);
+        assert_eq!(user_data.scaled_debt, Uint128::new(1030));
+        let loan = LOANS.load(&deps.storage, (&user, 0)).unwrap();
+        assert_eq!(loan.principal, Uint128::new(1000));
+        assert_eq!(loan.scaled_debt, Uint128::new(1030));
+        assert_eq!(MARKET_STATE.load(&deps.storage).unwrap().total_scaled_debt, Uint128::new(1030));
+
+        // the layout is current, migrating again changes nothing
+        migrate(deps.as_mut(), env, MigrateMsg { v0_1: None }).unwrap();
+    }
+
+    #[test]
+    fn migrate_from_v0_1_rate() {
+        let mut deps = mock_dependencies();
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(86400 * 365);
+        let user = Addr::unchecked("user");
+        save_v0_1_state(&mut deps, &user);
+        let params = MigrateFromV0_1 {
+            interest_rate_model: InterestRateModelConfig::Linear(LinearRateModel {
+                base_rate: Decimal::percent(2),
+                slope: Decimal::percent(10),
+            }),
+            ..v0_1_params()
+        };
+
+        mock_oracle_price(&mut deps, Decimal::one(), env.block.time);
+        migrate(deps.as_mut(), env.clone(), MigrateMsg { v0_1: Some(params) }).unwrap();
+        assert_eq!(COLLATERAL_STATE.load(&deps.storage, "generic").unwrap().last_price, Decimal::one());
+
+        // 1030 owed against 2000 deposited is 51.5% utilized, not 100%
+        let market_state = get_market_state(deps.as_ref(), env.clone()).unwrap();
+        assert_eq!(market_state.utilization, Decimal::permille(515));
+        assert_eq!(market_state.borrow_rate, Decimal::from_ratio(715_u128, 10000_u128));
+
+        // and the first accrual charges that rate
+        env.block.time = env.block.time.plus_seconds(86400 * 365);
+        let market_state = get_market_state(deps.as_ref(), env).unwrap();
+        assert_eq!(market_state.total_borrows, Uint128::new(1103));
+    }
+
+    #[test]
+    fn migrate_checks_version() {
+        let mut deps = mock_dependencies();
+
+        set_contract_version(deps.as_mut().storage, "crates.io:other-app", "0.1.0").unwrap();
+        migrate(deps.as_mut(), mock_env(), MigrateMsg { v0_1: Some(v0_1_params()) }).unwrap_err();
+
+        set_contract_version(deps.as_mut().storage, "crates.io:lending-app", "99.0.0").unwrap();
+        migrate(deps.as_mut(), mock_env(), MigrateMsg { v0_1: None }).unwrap_err();
+    }
+}
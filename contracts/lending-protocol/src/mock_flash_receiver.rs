@@ -0,0 +1,48 @@
+#![cfg(test)]
+
+//! Minimal flash loan receiver used by the multi-test integration tests
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{attr, from_binary, Binary, Deps, DepsMut, Empty, Env, MessageInfo, Response, StdError, StdResult, Uint128};
+use cw20::{Cw20Contract, Cw20ExecuteMsg};
+use cw_multi_test::{Contract, ContractWrapper};
+
+use crate::msg::FlashLoanReceiverMsg;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MockFlashReceiverExecuteMsg {
+    /// Sends `amount` of the CW20 `token` back to the lender, carried in the
+    /// callback's `msg`
+    Repay { token: String, amount: Uint128 },
+}
+
+fn instantiate(_deps: DepsMut, _env: Env, _info: MessageInfo, _msg: Empty) -> StdResult<Response> {
+    Ok(Response::default())
+}
+
+fn execute(deps: DepsMut, _env: Env, info: MessageInfo, msg: FlashLoanReceiverMsg) -> StdResult<Response> {
+    let FlashLoanReceiverMsg::FlashLoanCallback { amount: lent, fee, msg } = msg;
+    match from_binary(&msg)? {
+        MockFlashReceiverExecuteMsg::Repay { token, amount } => {
+            let token_addr = deps.api.addr_validate(&token)?;
+            let transfer = Cw20Contract(token_addr).call(
+                Cw20ExecuteMsg::Transfer { recipient: info.sender.to_string(), amount }
+            )?;
+            Ok(Response::new()
+                .add_message(transfer)
+                .add_attributes(vec![attr("lent", lent.to_string()), attr("fee", fee.to_string())]))
+        }
+    }
+}
+
+fn query(_deps: Deps, _env: Env, _msg: Empty) -> StdResult<Binary> {
+    Err(StdError::generic_err("no queries"))
+}
+
+pub fn contract_mock_flash_receiver() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(execute, instantiate, query);
+    Box::new(contract)
+}
@@ -0,0 +1,45 @@
+#![cfg(test)]
+
+//! Minimal price oracle used by the multi-test integration tests
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{to_binary, Binary, Decimal, Deps, DepsMut, Empty, Env, MessageInfo, Response, StdResult};
+use cw_multi_test::{Contract, ContractWrapper};
+use cw_storage_plus::Map;
+
+use crate::oracle::{OracleQueryMsg, PriceResponse};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MockOracleExecuteMsg {
+    /// Sets the price of `asset`, stamped with the current block time
+    SetPrice { asset: String, price: Decimal },
+}
+
+const PRICES: Map<&str, PriceResponse> = Map::new("prices");
+
+fn instantiate(_deps: DepsMut, _env: Env, _info: MessageInfo, _msg: Empty) -> StdResult<Response> {
+    Ok(Response::default())
+}
+
+fn execute(deps: DepsMut, env: Env, _info: MessageInfo, msg: MockOracleExecuteMsg) -> StdResult<Response> {
+    match msg {
+        MockOracleExecuteMsg::SetPrice { asset, price } => {
+            PRICES.save(deps.storage, &asset, &PriceResponse { price, last_updated: env.block.time.seconds() })?;
+            Ok(Response::default())
+        }
+    }
+}
+
+fn query(deps: Deps, _env: Env, msg: OracleQueryMsg) -> StdResult<Binary> {
+    match msg {
+        OracleQueryMsg::Price { asset } => to_binary(&PRICES.load(deps.storage, &asset)?),
+    }
+}
+
+pub fn contract_mock_oracle() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(execute, instantiate, query);
+    Box::new(contract)
+}
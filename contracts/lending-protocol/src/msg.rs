@@ -1,23 +1,110 @@
-use cosmwasm_std::{Uint128};
+use cosmwasm_std::{Addr, Binary, Decimal, Uint128};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cw20::Cw20ReceiveMsg;
+use cw2::ContractVersion;
+use cw20::{Cw20ReceiveMsg, Expiration};
+
+use crate::interest_rate::InterestRateModelConfig;
+use crate::state::{CollateralDeposit, Config, LoanInfo, MarketConfig, Role};
 
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 pub struct InstantiateMsg {
     pub admin: String,
+    /// first collateral market, listed with the parameters below
     pub generic_token: String,
+    /// price oracle for collateral tokens, see `oracle::OracleQueryMsg`
+    pub oracle: String,
+    /// max age in seconds of an oracle price before it is rejected
+    pub max_price_age: u64,
+    pub collateral_factor: Decimal,
+    pub liquidation_threshold: Decimal,
+    pub liquidation_bonus: Decimal,
+    pub close_factor: Decimal,
+    /// share of repaid interest kept as protocol reserves
+    pub reserve_factor: Decimal,
+    pub treasury: String,
+    /// fixed yearly rate of term loans
+    pub term_loan_rate: Decimal,
+    /// extra yearly rate charged on term loans past maturity and grace period
+    pub penalty_rate: Decimal,
+    /// seconds after maturity before the penalty rate applies
+    pub grace_period: u64,
+    /// share of a flash loan charged as a fee
+    pub flash_loan_fee: Decimal,
+    pub interest_rate_model: InterestRateModelConfig,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct MigrateMsg {
+    /// parameters introduced since 0.1.0, required to migrate from it
+    pub v0_1: Option<MigrateFromV0_1>,
+}
+
+/// Config and risk parameters the 0.1.0 layout has no equivalent for, see
+/// `InstantiateMsg`
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct MigrateFromV0_1 {
+    pub oracle: String,
+    pub max_price_age: u64,
+    pub collateral_factor: Decimal,
+    pub liquidation_threshold: Decimal,
+    pub liquidation_bonus: Decimal,
+    pub close_factor: Decimal,
+    pub reserve_factor: Decimal,
+    pub treasury: String,
+    pub term_loan_rate: Decimal,
+    pub penalty_rate: Decimal,
+    pub grace_period: u64,
+    pub flash_loan_fee: Decimal,
+    pub interest_rate_model: InterestRateModelConfig,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 pub enum Cw20HookMsg {
-    /// Deposit generic token
+    /// Deposit a listed collateral token
     Deposit {},
 
-    /// Payoff loan
-    Payoff {},
+    /// Deposit a listed collateral token on behalf of `beneficiary`
+    DepositFor { beneficiary: String },
+
+    /// Payoff loans, only `loan_id` when set, otherwise in the order given
+    /// by `policy` (oldest first by default)
+    Payoff { loan_id: Option<Uint128>, policy: Option<RepaymentPolicy> },
+
+    /// Payoff `borrower`'s loans as `Payoff` does, refunding any
+    /// overpayment to the payer
+    PayoffFor { borrower: String, loan_id: Option<Uint128>, policy: Option<RepaymentPolicy> },
+
+    /// Repay an undercollateralized borrower's loan and seize their
+    /// `collateral` tokens
+    Liquidate { borrower: String, collateral: String },
+
+    /// Supply lending tokens in exchange for interest-bearing receipts
+    Supply {},
+
+    /// Redeem receipts for their share of the supplied lending tokens
+    Redeem {},
+}
+
+/// Order in which a payment is allocated across a borrower's loans
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RepaymentPolicy {
+    #[default]
+    OldestFirst,
+    NewestFirst,
+    HighestBalanceFirst,
+}
+
+/// Message a flash loan receiver is executed with once the loan is sent
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FlashLoanReceiverMsg {
+    /// `amount` was lent and `amount` plus `fee` is due back before the
+    /// callback returns, `msg` is the borrower's own instruction
+    FlashLoanCallback { amount: Uint128, fee: Uint128, msg: Binary },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -29,26 +116,384 @@ pub enum ExecuteMsg {
     ////////////////////
     /// User operations
     ////////////////////
-    Withdraw {amount: Uint128},
-    Borrow {amount: Uint128},
+    /// Deposit `info.funds` into native collateral markets
+    DepositNative {},
+    Withdraw {token: String, amount: Uint128},
+    /// Count deposits of `token` toward borrowing, or with `enabled` false
+    /// keep them as supply only, out of reach of liquidation. Refused if the
+    /// remaining collateral would not cover the sender's debt
+    SetCollateral {token: String, enabled: bool},
+    /// Borrow against deposits, open-ended at the variable rate or, with a
+    /// `term` in seconds, at the fixed term loan rate until maturity. With
+    /// `on_behalf_of` the loan is drawn on that address's collateral and
+    /// recorded as its debt, within the delegation it approved for the sender
+    Borrow {amount: Uint128, term: Option<u64>, on_behalf_of: Option<String>},
+    /// Let `delegate` borrow up to `limit` against the sender's collateral
+    /// until `expires`, replacing any earlier delegation. A zero `limit`
+    /// revokes it
+    ApproveBorrowDelegate {delegate: String, limit: Uint128, expires: Option<Expiration>},
+
+    /// Repay `amount` of the borrower's debt, burned from the sender's lending
+    /// token allowance, in exchange for the borrower's `collateral` tokens
+    Liquidate {borrower: String, collateral: String, amount: Uint128},
+
+    /// Lend `amount` of collateral `token` to `receiver` and execute
+    /// `FlashLoanReceiverMsg::FlashLoanCallback` carrying `msg` on it. By the
+    /// end of the callback the receiver must have sent back `amount` plus the
+    /// flash loan fee, or the whole transaction is reverted
+    FlashLoan {token: String, amount: Uint128, receiver: String, msg: Binary},
 
     ////////////////////
     /// Admin operations
     ////////////////////
-    SetLendingTokenAddress {address: String}
+    /// Propose `address` as the next admin, the current admin stays in
+    /// charge until it accepts
+    ProposeAdmin {address: String},
+    /// Accept a proposed admin transfer, sent by the proposed admin
+    AcceptAdmin {},
+    /// Give up admin rights for good, granted roles are kept
+    RenounceAdmin {},
+    /// Grant `role` to `address`, replacing any role it held
+    GrantRole {address: String, role: Role},
+    RevokeRole {address: String},
+    /// Pause (true) or resume (false) operations, sent by the pause guardian
+    SetPaused {
+        deposit: Option<bool>,
+        borrow: Option<bool>,
+        withdraw: Option<bool>,
+        payoff: Option<bool>,
+        liquidate: Option<bool>,
+    },
+    SetLendingTokenAddress {address: String},
+    SetReceiptTokenAddress {address: String},
+    UpdateConfig {
+        oracle: Option<String>,
+        max_price_age: Option<u64>,
+        close_factor: Option<Decimal>,
+        reserve_factor: Option<Decimal>,
+        treasury: Option<String>,
+        term_loan_rate: Option<Decimal>,
+        penalty_rate: Option<Decimal>,
+        grace_period: Option<u64>,
+        flash_loan_fee: Option<Decimal>,
+    },
+    /// List `token` as collateral, or update the risk parameters of a listed
+    /// token. `token` is a bank denom when `native` is set, else a CW20
+    /// address, which cannot change once it is listed
+    ListMarket {
+        token: String,
+        native: bool,
+        collateral_factor: Decimal,
+        liquidation_threshold: Decimal,
+        liquidation_bonus: Decimal,
+        /// max total deposits of the token, unlimited when unset
+        supply_cap: Option<Uint128>,
+    },
+    /// Set the max total lending tokens owed by borrowers, unlimited when unset
+    SetBorrowCap {borrow_cap: Option<Uint128>},
+    UpdateInterestRateModel {model: InterestRateModelConfig},
+    /// Send `amount` of the reserves to `recipient`, the treasury by default
+    WithdrawReserves {amount: Uint128, recipient: Option<String>},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
     GetUserInfo { address: String },
+    /// Oracle price of a collateral token used for collateral valuation
+    /// Return type: oracle::PriceResponse
+    CollateralPrice { token: String },
+    /// Listed collateral markets
+    /// Return type: MarketsResponse
+    Markets {},
+    /// Current interest rate model and the borrow rate it yields
+    /// Return type: InterestRateResponse
+    InterestRate {},
+    /// Supply pool totals and the receipt exchange rate
+    /// Return type: SupplyInfoResponse
+    SupplyInfo {},
+    /// Protocol reserves and where they are withdrawn to
+    /// Return type: ReservesResponse
+    Reserves {},
+    /// Current and proposed admin
+    /// Return type: AdminResponse
+    Admin {},
+    /// Addresses holding a role
+    /// Return type: RolesResponse
+    Roles {},
+    /// Operations currently paused
+    /// Return type: state::PauseState
+    PauseStatus {},
+    /// Borrow cap and supply caps with what is left of them
+    /// Return type: CapacityResponse
+    Capacity {},
+    /// Solvency of an account as borrowing and withdrawing see it
+    /// Return type: AccountLiquidityResponse
+    AccountLiquidity { address: String },
+    /// Addresses that have deposited or borrowed, in address order
+    /// Return type: AllUsersResponse
+    AllUsers { start_after: Option<String>, limit: Option<u32> },
+    /// Open loans of `address` with the amounts owed now, in loan id order
+    /// Return type: LoansResponse
+    Loans { address: String, start_after: Option<Uint128>, limit: Option<u32> },
+    /// Addresses allowed to borrow against the collateral of `owner`, in
+    /// address order
+    /// Return type: BorrowDelegationsResponse
+    BorrowDelegations { owner: String, start_after: Option<String>, limit: Option<u32> },
+    /// Protocol configuration and contract version
+    /// Return type: ConfigResponse
+    Config {},
+    /// Pool totals with interest accrued up to now, and current rates
+    /// Return type: MarketStateResponse
+    MarketState {},
+    /// Position of `address` after a `Borrow`, or the error it would fail with
+    /// Return type: AccountLiquidityResponse
+    SimulateBorrow { address: String, amount: Uint128, term: Option<u64> },
+    /// What the loans of `address` will owe at `at_time`, in seconds, if the
+    /// market is left as it is until then
+    /// Return type: ProjectedDebtResponse
+    ProjectedDebt { address: String, at_time: u64 },
+    /// Position of `address` after a `Withdraw`, or the error it would fail with
+    /// Return type: AccountLiquidityResponse
+    SimulateWithdraw { address: String, token: String, amount: Uint128 },
+    /// How a `Payoff` sent by `address` would be split across its loans
+    /// Return type: SimulatePayoffResponse
+    SimulatePayoff {
+        address: String,
+        amount: Uint128,
+        loan_id: Option<Uint128>,
+        policy: Option<RepaymentPolicy>,
+    },
 }
 
 // We define a custom struct for each query response
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct UserInfoResponse {
-    pub generic_token_deposited: Uint128,
+    /// deposits including the fees they have earned
+    pub deposits: Vec<CollateralDeposit>,
+    /// deposit receipts held per token, each a share of the market's deposits
+    pub deposit_receipts: Vec<CollateralDeposit>,
     pub lending_token_withdrawed: Uint128,
     pub total_loan_owed: Uint128,
+    /// deposits valued in lending tokens at current oracle prices
+    pub collateral_value: Uint128,
+    /// set when an oracle price was stale or invalid, and `collateral_value`
+    /// uses the last price the contract accepted for that token instead
+    pub price_stale: bool,
+    /// price each deposit in `collateral_value` was valued at
+    pub prices: Vec<DepositPrice>,
+    /// tokens deposited as supply only, left out of `collateral_value`
+    pub supply_only: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct DepositPrice {
+    pub token: String,
+    pub price: Decimal,
+    /// the oracle price was stale or invalid and `price` is the last one the
+    /// contract accepted
+    pub stale: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct InterestRateResponse {
+    pub model: InterestRateModelConfig,
+    /// total borrowed / deposits valued at the last seen oracle prices
+    pub utilization: Decimal,
+    /// yearly borrow rate at `utilization`
+    pub borrow_rate: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SupplyInfoResponse {
+    pub receipt_token: Option<Addr>,
+    /// lending tokens owed to receipt holders, including interest earned
+    pub total_supply: Uint128,
+    pub total_receipts: Uint128,
+    /// lending tokens redeemable per receipt
+    pub exchange_rate: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct MarketInfo {
+    pub token: String,
+    pub config: MarketConfig,
+    pub total_deposits: Uint128,
+    /// oracle price last used to value the market's deposits
+    pub last_price: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct MarketsResponse {
+    pub markets: Vec<MarketInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ReservesResponse {
+    pub treasury: Addr,
+    pub reserve_factor: Decimal,
+    /// lending tokens held as reserves
+    pub total_reserves: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AdminResponse {
+    pub admin: Option<Addr>,
+    pub pending_admin: Option<Addr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RoleInfo {
+    pub address: Addr,
+    pub role: Role,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RolesResponse {
+    pub roles: Vec<RoleInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct MarketCapacity {
+    pub token: String,
+    pub supply_cap: Option<Uint128>,
+    pub total_deposits: Uint128,
+    /// deposits still accepted, unlimited when unset
+    pub remaining: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CapacityResponse {
+    pub borrow_cap: Option<Uint128>,
+    pub total_borrows: Uint128,
+    /// lending tokens still available to borrow, unlimited when unset
+    pub remaining_borrows: Option<Uint128>,
+    pub markets: Vec<MarketCapacity>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AccountLiquidityResponse {
+    /// deposits valued in lending tokens at current oracle prices
+    pub collateral_value: Uint128,
+    /// lending tokens owed across all loans
+    pub debt_value: Uint128,
+    /// liquidation limit over debt, liquidatable below one, unset without debt
+    pub health_factor: Option<Decimal>,
+    /// lending tokens that can still be borrowed
+    pub max_borrow: Uint128,
+    /// collateral that can be withdrawn from each deposit on its own
+    pub max_withdraw: Vec<CollateralDeposit>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AllUsersResponse {
+    pub users: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LoanResponse {
+    pub loan_id: Uint128,
+    /// loan with term loan interest accrued up to now
+    pub loan: LoanInfo,
+    pub amount_owed: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LoansResponse {
+    pub loans: Vec<LoanResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct BorrowDelegationInfo {
+    pub delegate: String,
+    /// amount the delegate can still borrow
+    pub limit: Uint128,
+    pub expires: Expiration,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct BorrowDelegationsResponse {
+    pub delegations: Vec<BorrowDelegationInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ConfigResponse {
+    pub config: Config,
+    pub contract_version: ContractVersion,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct MarketStateResponse {
+    /// deposits held in each collateral market
+    pub total_deposits: Vec<CollateralDeposit>,
+    /// deposits valued at the last seen oracle prices
+    pub total_collateral_value: Uint128,
+    /// lending tokens owed by borrowers, including accrued interest
+    pub total_borrows: Uint128,
+    /// lending tokens owed to receipt holders
+    pub total_supply: Uint128,
+    pub total_reserves: Uint128,
+    pub utilization: Decimal,
+    /// yearly variable borrow rate
+    pub borrow_rate: Decimal,
+    /// yearly rate suppliers earn from variable rate interest, net of reserves
+    pub supply_rate: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LoanPayment {
+    pub loan_id: Uint128,
+    pub amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SimulatePayoffResponse {
+    /// amount applied to each loan, in the order it is applied
+    pub allocations: Vec<LoanPayment>,
+    /// part of the payment sent back once every loan is closed
+    pub refund_amount: Uint128,
+    pub position: AccountLiquidityResponse,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ProjectedLoan {
+    pub loan_id: Uint128,
+    pub amount_owed: Uint128,
+    /// yearly rate the loan accrues at by `at_time`, penalty included
+    pub rate: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ProjectedDebtResponse {
+    pub at_time: u64,
+    pub loans: Vec<ProjectedLoan>,
+    pub total_owed: Uint128,
+    /// loan rates at `at_time` weighted by what each loan owes then
+    pub apr: Decimal,
+    /// `apr` compounded daily
+    pub apy: Decimal,
 }
@@ -0,0 +1,54 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{Addr, Decimal, Env, QuerierWrapper, Uint128};
+
+use crate::error::ContractError;
+
+/// Query interface the price oracle contract must implement
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OracleQueryMsg {
+    /// Price of `asset` denominated in the lending token
+    Price { asset: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PriceResponse {
+    pub price: Decimal,
+    /// block time (in seconds) the price was last updated at
+    pub last_updated: u64,
+}
+
+/// Queries the oracle for the price of `asset`, rejecting prices that are
+/// zero or older than `max_age` seconds
+pub fn query_price(
+    querier: &QuerierWrapper,
+    env: &Env,
+    oracle: &Addr,
+    asset: &str,
+    max_age: u64,
+) -> Result<PriceResponse, ContractError> {
+    let res: PriceResponse = querier.query_wasm_smart(
+        oracle,
+        &OracleQueryMsg::Price { asset: asset.to_string() },
+    )?;
+    if res.price.is_zero() {
+        return Err(ContractError::InvalidPrice {});
+    }
+    if env.block.time.seconds().saturating_sub(res.last_updated) > max_age {
+        return Err(ContractError::StalePrice { last_updated: res.last_updated });
+    }
+    Ok(res)
+}
+
+/// Value of `amount` of the asset in lending tokens
+pub fn to_value(amount: Uint128, price: Decimal) -> Uint128 {
+    amount * price
+}
+
+/// Amount of the asset worth `value` lending tokens
+pub fn from_value(value: Uint128, price: Decimal) -> Uint128 {
+    value.multiply_ratio(Decimal::one().atomics(), price.atomics())
+}
@@ -0,0 +1,133 @@
+//! Previews of user operations, run through the execute handlers against
+//! storage whose writes are thrown away
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use cosmwasm_std::{Deps, DepsMut, Env, MessageInfo, Order, Record, StdError, StdResult, Storage, Uint128};
+
+use crate::contract::{get_account_liquidity, payoff, try_borrow, try_withdraw};
+use crate::msg::{AccountLiquidityResponse, LoanPayment, RepaymentPolicy, SimulatePayoffResponse};
+
+/// Storage that reads through to `base` and keeps every write to itself
+struct SimulatedStorage<'a> {
+    base: &'a dyn Storage,
+    /// values written so far, `None` for removed keys
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'a> SimulatedStorage<'a> {
+    fn new(base: &'a dyn Storage) -> Self {
+        SimulatedStorage { base, writes: BTreeMap::new() }
+    }
+}
+
+impl Storage for SimulatedStorage<'_> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match self.writes.get(key) {
+            Some(value) => value.clone(),
+            None => self.base.get(key),
+        }
+    }
+
+    fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'b> {
+        let mut records: BTreeMap<Vec<u8>, Vec<u8>> = self.base.range(start, end, Order::Ascending).collect();
+        let bounds = (
+            start.map_or(Bound::Unbounded, |start| Bound::Included(start.to_vec())),
+            end.map_or(Bound::Unbounded, |end| Bound::Excluded(end.to_vec())),
+        );
+        for (key, value) in self.writes.range(bounds) {
+            match value {
+                Some(value) => records.insert(key.clone(), value.clone()),
+                None => records.remove(key),
+            };
+        }
+        match order {
+            Order::Ascending => Box::new(records.into_iter()),
+            Order::Descending => Box::new(records.into_iter().rev()),
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.writes.insert(key.to_vec(), Some(value.to_vec()));
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.writes.insert(key.to_vec(), None);
+    }
+}
+
+fn simulated_deps<'a>(deps: Deps<'a>, storage: &'a mut SimulatedStorage) -> DepsMut<'a> {
+    DepsMut { storage, api: deps.api, querier: deps.querier }
+}
+
+fn simulated_info(deps: Deps, address: &str) -> StdResult<MessageInfo> {
+    Ok(MessageInfo { sender: deps.api.addr_validate(address)?, funds: vec![] })
+}
+
+/// Position of `address` after borrowing `amount`
+pub fn simulate_borrow(
+    deps: Deps,
+    env: Env,
+    address: String,
+    amount: Uint128,
+    term: Option<u64>,
+) -> StdResult<AccountLiquidityResponse> {
+    let info = simulated_info(deps, &address)?;
+    let mut storage = SimulatedStorage::new(deps.storage);
+    try_borrow(simulated_deps(deps, &mut storage), info, env.clone(), amount, term, None)
+        .map_err(|err| StdError::generic_err(err.to_string()))?;
+    get_account_liquidity(Deps { storage: &storage, ..deps }, env, address)
+}
+
+/// Position of `address` after withdrawing `amount` of `token`
+pub fn simulate_withdraw(
+    deps: Deps,
+    env: Env,
+    address: String,
+    token: String,
+    amount: Uint128,
+) -> StdResult<AccountLiquidityResponse> {
+    let info = simulated_info(deps, &address)?;
+    let mut storage = SimulatedStorage::new(deps.storage);
+    try_withdraw(simulated_deps(deps, &mut storage), info, env.clone(), token, amount)
+        .map_err(|err| StdError::generic_err(err.to_string()))?;
+    get_account_liquidity(Deps { storage: &storage, ..deps }, env, address)
+}
+
+/// Split of `address` paying off `amount` across its loans, and the
+/// position it leaves
+pub fn simulate_payoff(
+    deps: Deps,
+    env: Env,
+    address: String,
+    amount: Uint128,
+    loan_id: Option<Uint128>,
+    policy: Option<RepaymentPolicy>,
+) -> StdResult<SimulatePayoffResponse> {
+    let info = simulated_info(deps, &address)?;
+    let mut storage = SimulatedStorage::new(deps.storage);
+    let loan_id = loan_id.map(|loan_id| loan_id.u128());
+    let (repayment, _) = payoff(
+        simulated_deps(deps, &mut storage),
+        &env,
+        &info.sender,
+        &info.sender,
+        amount,
+        loan_id,
+        policy.unwrap_or_default(),
+    )
+    .map_err(|err| StdError::generic_err(err.to_string()))?;
+    Ok(SimulatePayoffResponse {
+        allocations: repayment.allocations.into_iter()
+            .map(|(loan_id, amount)| LoanPayment { loan_id: loan_id.into(), amount })
+            .collect(),
+        refund_amount: repayment.leftover,
+        position: get_account_liquidity(Deps { storage: &storage, ..deps }, env, address)?,
+    })
+}
@@ -1,163 +1,678 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Uint128, Timestamp, Decimal};
+use cosmwasm_std::{Addr, Uint128, Timestamp, Decimal, Empty};
+use cw20::Expiration;
 use cw_storage_plus::{Item, Map};
 
+use crate::interest_rate::InterestRateModelConfig;
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
-    pub admin: Addr,
-    pub generic_token: Addr,
+    /// holds every role, none once renounced
+    pub admin: Option<Addr>,
+    /// proposed admin, takes over once it accepts
+    pub pending_admin: Option<Addr>,
     pub lending_token: Option<Addr>,
+    /// interest-bearing receipt minted to suppliers of lending tokens
+    pub receipt_token: Option<Addr>,
+    /// prices collateral tokens in units of the lending token
+    pub oracle: Addr,
+    /// max age in seconds of an oracle price before it is rejected
+    pub max_price_age: u64,
+    /// max share of a borrower's debt that can be repaid in one liquidation
+    pub close_factor: Decimal,
+    /// share of accrued interest kept as protocol reserves
+    pub reserve_factor: Decimal,
+    /// default recipient of withdrawn reserves
+    pub treasury: Addr,
+    /// fixed yearly rate of term loans taken out from now on
+    pub term_loan_rate: Decimal,
+    /// extra yearly rate charged on term loans once their grace period ends
+    pub penalty_rate: Decimal,
+    /// seconds after maturity before the penalty rate applies
+    pub grace_period: u64,
+    /// share of a flash loan charged as a fee, paid to the market's
+    /// depositors less the reserve factor's share for the treasury
+    pub flash_loan_fee: Decimal,
+    /// max total lending tokens owed by borrowers, unlimited when unset
+    pub borrow_cap: Option<Uint128>,
+}
+
+/// Operator role granted by the admin, the admin itself holds every role
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// lists markets and updates the interest rate model
+    RiskManager,
+    /// pauses protocol operations
+    PauseGuardian,
+    /// withdraws protocol reserves
+    Treasurer,
+}
+
+/// Operations halted by the pause guardian.
+///
+/// Flags are independent, repayments and debt-free withdrawals stay open
+/// while borrowing is paused.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct PauseState {
+    /// collateral deposits
+    pub deposit: bool,
+    /// borrows, flash loans, and withdrawals by users with debt
+    pub borrow: bool,
+    /// collateral withdrawals
+    pub withdraw: bool,
+    pub payoff: bool,
+    pub liquidate: bool,
+}
+
+/// Risk parameters of a listed collateral token
+///
+/// Markets are keyed by the CW20 contract address, or by the bank denom for
+/// native markets.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarketConfig {
+    /// collateral is a native bank denom rather than a CW20 token
+    pub native: bool,
+    /// share of the deposit that can be borrowed against (loan-to-value)
+    pub collateral_factor: Decimal,
+    /// share of the deposit the debt must exceed before it can be liquidated
+    pub liquidation_threshold: Decimal,
+    /// extra collateral paid to liquidators on top of the repaid debt
+    pub liquidation_bonus: Decimal,
+    /// max total deposits of the token, unlimited when unset
+    pub supply_cap: Option<Uint128>,
+}
+
+/// Deposits held for a listed collateral token.
+///
+/// Depositors hold receipts for a share of `total_deposits`, so fees earned
+/// on the deposits raise what each receipt is worth.
+///
+/// `last_price` is the oracle price last seen for the token, it values the
+/// deposits for utilization so accruing interest needs no oracle query.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct CollateralState {
+    pub total_deposits: Uint128,
+    /// deposit receipts outstanding
+    pub total_receipts: Uint128,
+    pub last_price: Decimal,
+}
+
+impl CollateralState {
+    /// Receipts minted for depositing `amount`, rounded down
+    pub fn receipts_for(&self, amount: Uint128) -> Uint128 {
+        if self.total_receipts.is_zero() {
+            return amount;
+        }
+        amount.multiply_ratio(self.total_receipts, self.total_deposits)
+    }
+
+    /// Receipts burned for withdrawing `amount`, rounded up
+    pub fn receipts_to_withdraw(&self, amount: Uint128) -> Uint128 {
+        let receipts = self.receipts_for(amount);
+        if self.underlying_for(receipts) < amount {
+            receipts + Uint128::new(1)
+        } else {
+            receipts
+        }
+    }
+
+    /// Deposit that `receipts` are worth, rounded down
+    pub fn underlying_for(&self, receipts: Uint128) -> Uint128 {
+        if self.total_receipts.is_zero() {
+            return receipts;
+        }
+        receipts.multiply_ratio(self.total_deposits, self.total_receipts)
+    }
+}
+
+pub const SECONDS_PER_YEAR: u64 = 365 * 86400;
+
+/// Aggregate pool totals and the market-wide borrow index.
+///
+/// Debt is stored scaled down by the borrow index at the time it was taken
+/// out, so multiplying any scaled amount by the current index gives the
+/// amount owed including interest.
+///
+/// Suppliers hold receipts for a share of `total_supply`, the lending tokens
+/// they supplied plus the interest earned on what the pool lent out. Loans
+/// are funded from the pool's cash first and minted for the rest, and
+/// interest is credited to suppliers and reserves as it accrues, so the
+/// exchange rate rises continuously and only for the time a supplier was in.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarketState {
+    pub total_scaled_debt: Uint128,
+    /// cumulative interest factor since the market was created
+    pub borrow_index: Decimal,
+    pub last_accrual_time: Timestamp,
+    /// lending tokens owed to receipt holders
+    pub total_supply: Uint128,
+    /// receipt tokens outstanding
+    pub total_receipts: Uint128,
+    /// lending tokens held as protocol reserves
+    pub total_reserves: Uint128,
+    /// outstanding principal of term loans, which accrue outside the index
+    pub total_term_principal: Uint128,
+    /// yearly interest of the open term loans at their fixed rates
+    pub term_interest_per_year: Uint128,
+    /// lending tokens held for suppliers and reserves
+    pub total_cash: Uint128,
+    /// principal lent out of `total_cash` and not yet repaid, repayments
+    /// refill the pool before anything is burned
+    pub total_lent: Uint128,
+    /// interest credited to suppliers and reserves that borrowers have yet
+    /// to pay
+    pub interest_receivable: Uint128,
+}
+
+impl MarketState {
+    pub fn new(ts: Timestamp) -> MarketState {
+        MarketState {
+            total_scaled_debt: Uint128::zero(),
+            borrow_index: Decimal::one(),
+            last_accrual_time: ts,
+            total_supply: Uint128::zero(),
+            total_receipts: Uint128::zero(),
+            total_reserves: Uint128::zero(),
+            total_term_principal: Uint128::zero(),
+            term_interest_per_year: Uint128::zero(),
+            total_cash: Uint128::zero(),
+            total_lent: Uint128::zero(),
+            interest_receivable: Uint128::zero(),
+        }
+    }
+
+    /// Total debt, including interest accrued through the borrow index
+    pub fn total_borrows(&self) -> Uint128 {
+        self.unscale(self.total_scaled_debt) + self.total_term_principal
+    }
+
+    /// Share of `collateral_value` that is borrowed, capped at 1
+    pub fn utilization(&self, collateral_value: Uint128) -> Decimal {
+        let total_borrows = self.total_borrows();
+        if collateral_value.is_zero() || total_borrows >= collateral_value {
+            return if total_borrows.is_zero() { Decimal::zero() } else { Decimal::one() };
+        }
+        Decimal::from_ratio(total_borrows, collateral_value)
+    }
+
+    /// Grows the borrow index by the yearly `rate` over the time elapsed
+    /// since the last accrual, and credits the interest it adds: the
+    /// `reserve_factor` share to the reserves and, of the rest, the share
+    /// funded by the pool to suppliers. Interest on minted debt is burned
+    /// when it is paid.
+    pub fn accrue(&mut self, ts: Timestamp, rate: Decimal, reserve_factor: Decimal) {
+        if ts <= self.last_accrual_time {
+            return;
+        }
+        let elapsed = ts.seconds() - self.last_accrual_time.seconds();
+        let total_borrows = self.total_borrows();
+        let variable_debt = self.unscale(self.total_scaled_debt);
+        let factor = Decimal::one() + rate * Decimal::from_ratio(elapsed, SECONDS_PER_YEAR);
+        self.borrow_index *= factor;
+        self.last_accrual_time = ts;
+
+        let interest = self.unscale(self.total_scaled_debt) - variable_debt
+            + self.term_interest_per_year.multiply_ratio(elapsed, SECONDS_PER_YEAR);
+        let reserve_share = interest * reserve_factor;
+        let supplier_share = if self.total_receipts.is_zero() || total_borrows.is_zero() {
+            Uint128::zero()
+        } else {
+            (interest - reserve_share).multiply_ratio(std::cmp::min(self.total_lent, total_borrows), total_borrows)
+        };
+        self.total_reserves += reserve_share;
+        self.total_supply += supplier_share;
+        self.interest_receivable += reserve_share + supplier_share;
+    }
+
+    /// Takes `amount` out of the pool's cash for a payout to suppliers or
+    /// reserves, returning the part that has to be minted because the cash
+    /// is lent out or its interest not yet paid. The minted part takes over
+    /// that much of the pool's claim on borrowers, so their repayment of it
+    /// is burned.
+    pub fn pay_out(&mut self, amount: Uint128) -> Uint128 {
+        let from_cash = std::cmp::min(amount, self.total_cash);
+        self.total_cash -= from_cash;
+        let minted = amount - from_cash;
+        let from_lent = std::cmp::min(minted, self.total_lent);
+        self.total_lent -= from_lent;
+        self.interest_receivable = self.interest_receivable.saturating_sub(minted - from_lent);
+        minted
+    }
+
+    /// Scaled debt for `amount`, rounded up so borrowers never owe less than
+    /// they took out
+    pub fn scale(&self, amount: Uint128) -> Uint128 {
+        let scaled = amount.multiply_ratio(Decimal::one().atomics(), self.borrow_index.atomics());
+        if scaled * self.borrow_index < amount {
+            return scaled + Uint128::from(1_u128);
+        }
+        scaled
+    }
+
+    /// Scaled debt cleared by repaying `amount`, rounded down
+    pub fn scale_repayment(&self, amount: Uint128) -> Uint128 {
+        amount.multiply_ratio(Decimal::one().atomics(), self.borrow_index.atomics())
+    }
+
+    /// Amount owed for `scaled` debt at the current index
+    pub fn unscale(&self, scaled: Uint128) -> Uint128 {
+        scaled * self.borrow_index
+    }
+
+    /// Lending tokens redeemable per receipt
+    pub fn exchange_rate(&self) -> Decimal {
+        if self.total_receipts.is_zero() {
+            return Decimal::one();
+        }
+        Decimal::from_ratio(self.total_supply, self.total_receipts)
+    }
+
+    /// Receipts minted for supplying `amount`, rounded down
+    pub fn receipts_for(&self, amount: Uint128) -> Uint128 {
+        if self.total_receipts.is_zero() {
+            return amount;
+        }
+        amount.multiply_ratio(self.total_receipts, self.total_supply)
+    }
+
+    /// Lending tokens paid out for redeeming `receipts`, rounded down
+    pub fn underlying_for(&self, receipts: Uint128) -> Uint128 {
+        receipts.multiply_ratio(self.total_supply, self.total_receipts)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CollateralDeposit {
+    /// CW20 contract address or native denom of the market
+    pub token: String,
+    pub amount: Uint128,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct UserData {
-    pub generic_token_deposited: Uint128,
+    /// deposit receipts per collateral token, tokens without a deposit are
+    /// left out
+    pub deposits: Vec<CollateralDeposit>,
     pub borrow_amt: Uint128,
     pub curr_loan_id: Uint128,
+    /// sum of the scaled debt of all the user's loans
+    pub scaled_debt: Uint128,
+    /// tokens whose deposits are supplied without backing the user's debt
+    #[serde(default)]
+    pub supply_only: Vec<String>,
+}
+
+impl Default for UserData {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl UserData {
     pub fn new() -> UserData {
         UserData { 
-            generic_token_deposited: Uint128::from(0_u128),
+            deposits: vec![],
             borrow_amt: Uint128::from(0_u128),
             curr_loan_id: Uint128::from(0_u128),
+            scaled_debt: Uint128::from(0_u128),
+            supply_only: vec![],
+        }
+    }
+
+    /// whether deposits of `token` count toward borrowing and liquidation
+    pub fn is_collateral(&self, token: &str) -> bool {
+        !self.supply_only.iter().any(|supply_only| supply_only == token)
+    }
+
+    /// deposits that back the user's debt
+    pub fn collateral(&self) -> Vec<CollateralDeposit> {
+        self.deposits.iter().filter(|deposit| self.is_collateral(&deposit.token)).cloned().collect()
+    }
+
+    pub fn set_collateral(&self, token: &str, enabled: bool) -> UserData {
+        let mut supply_only: Vec<_> = self.supply_only.iter().filter(|supply_only| *supply_only != token).cloned().collect();
+        if !enabled {
+            supply_only.push(token.to_string());
+        }
+        UserData { 
+            supply_only,
+            ..self.clone()
         }
     }
 
-    pub fn deposit_amount(&self, amount: Uint128) -> UserData {
+    /// deposit receipts held for `token`
+    pub fn deposited(&self, token: &str) -> Uint128 {
+        self.deposits.iter()
+            .find(|deposit| deposit.token == token)
+            .map(|deposit| deposit.amount)
+            .unwrap_or_default()
+    }
+
+    pub fn deposit_amount(&self, token: &str, amount: Uint128) -> UserData {
+        let mut deposits: Vec<_> = self.deposits.iter().filter(|deposit| deposit.token != token).cloned().collect();
+        deposits.push(CollateralDeposit { token: token.to_string(), amount: self.deposited(token) + amount });
         UserData { 
-            generic_token_deposited: self.generic_token_deposited + amount, 
-            borrow_amt: self.borrow_amt,
-            curr_loan_id: self.curr_loan_id,
+            deposits,
+            ..self.clone()
         }
     }
 
-    pub fn withdraw_amount(&self, amount: Uint128) -> UserData {
+    pub fn withdraw_amount(&self, token: &str, amount: Uint128) -> UserData {
+        let remaining = self.deposited(token) - amount;
+        let mut deposits: Vec<_> = self.deposits.iter().filter(|deposit| deposit.token != token).cloned().collect();
+        if !remaining.is_zero() {
+            deposits.push(CollateralDeposit { token: token.to_string(), amount: remaining });
+        }
         UserData { 
-            generic_token_deposited: self.generic_token_deposited - amount, 
-            borrow_amt: self.borrow_amt,
-            curr_loan_id: self.curr_loan_id,
+            deposits,
+            ..self.clone()
         }
     }
 
     /// update borrow amount & increment loan id
-    pub fn borrow_amount(&self, amount: Uint128) -> UserData {
+    pub fn borrow_amount(&self, amount: Uint128, scaled: Uint128) -> UserData {
         UserData { 
-            generic_token_deposited: self.generic_token_deposited, 
             borrow_amt: self.borrow_amt + amount, 
             curr_loan_id: self.curr_loan_id + Uint128::from(1_u128),
+            scaled_debt: self.scaled_debt + scaled,
+            ..self.clone()
         }
     }
+
+    pub fn repay_scaled(&self, scaled: Uint128) -> UserData {
+        UserData { 
+            scaled_debt: self.scaled_debt - scaled,
+            ..self.clone()
+        }
+    }
+}
+
+/// Repayment terms of a fixed-term loan, locked in when it is taken out
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LoanTerm {
+    pub maturity: Timestamp,
+    /// fixed yearly rate
+    pub rate: Decimal,
+    /// extra yearly rate once `grace_period` seconds past maturity have passed
+    pub penalty_rate: Decimal,
+    pub grace_period: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct LoanInfo {
     pub start_time: Timestamp,
+    /// principal still outstanding, repayments cover interest first
+    pub principal: Uint128,
+    /// debt scaled down by the borrow index, see `MarketState`
+    pub scaled_debt: Uint128,
+    /// set for fixed-term loans, which accrue at their own rate instead of
+    /// through the borrow index
+    pub term: Option<LoanTerm>,
+    /// unpaid interest of a term loan as of `last_update_time`
+    pub interest: Uint128,
     pub last_update_time: Timestamp,
-    pub principal: Decimal,
-    pub amount_owed: Decimal,
 }
 
 impl LoanInfo {
-    pub fn new(ts: Timestamp, principal: Uint128) -> LoanInfo {
+    pub fn new(ts: Timestamp, principal: Uint128, scaled_debt: Uint128) -> LoanInfo {
         LoanInfo {
             start_time: ts, 
-            last_update_time: ts, 
-            principal: Decimal::new(principal),
-            amount_owed: Decimal::new(principal),
-        }
-    }
-
-    /// Computes current loan values on granularity of days
-    pub fn update_loan(self, ts: Timestamp) -> LoanInfo {
-        let mut principal = self.principal;
-        let mut amount_owed = self.amount_owed;
-
-        let prev_days_elapsed = (self.last_update_time.seconds() - self.start_time.seconds()) / 86400;
-        let new_days_elapsed = (ts.seconds() - self.start_time.seconds()) / 86400;
-        let mut inbetween_days = new_days_elapsed - prev_days_elapsed;
-
-        let prev_year = prev_days_elapsed / 365;
-        let curr_year = new_days_elapsed / 365;
-        if prev_year != curr_year {
-            // find number of days to next year
-            let next_year_in_days = (prev_year + 1) * 365;
-            let apply_days = next_year_in_days - prev_days_elapsed;
-
-            // update principal
-            let p1 = Decimal::one() + Decimal::percent(3);
-            amount_owed = principal * p1;
-            principal = amount_owed;
-
-            // update number of days left to update;
-            inbetween_days -= apply_days;
-            while inbetween_days >= 365 {  
-                amount_owed = principal * p1;
-                principal = amount_owed;
-                inbetween_days -= 365;
-            }
-        }
-        if inbetween_days > 0 {
-            let p = Decimal::one() + (Decimal::percent(3) * Decimal::from_ratio(inbetween_days, 365_u128)); 
-            let amt = principal * p;
-            return LoanInfo {
-                start_time: self.start_time,
-                last_update_time: ts,
-                principal: principal,
-                amount_owed: amt,
-            }
-        }
-        return LoanInfo {
-            start_time: self.start_time,
+            principal,
+            scaled_debt,
+            term: None,
+            interest: Uint128::zero(),
             last_update_time: ts,
-            principal: principal,
-            amount_owed: amount_owed
+        }
+    }
+
+    pub fn new_term(ts: Timestamp, principal: Uint128, term: LoanTerm) -> LoanInfo {
+        LoanInfo {
+            term: Some(term),
+            ..LoanInfo::new(ts, principal, Uint128::zero())
+        }
+    }
+
+    /// Accrues a term loan's interest up to `ts`, at the fixed rate plus the
+    /// penalty rate for the time past maturity and the grace period.
+    /// Loans without a term accrue through the borrow index and are returned
+    /// unchanged.
+    pub fn update_loan(&self, ts: Timestamp) -> LoanInfo {
+        let term = match &self.term {
+            Some(term) if ts > self.last_update_time => term,
+            _ => return self.clone(),
+        };
+        let elapsed = ts.seconds() - self.last_update_time.seconds();
+        let penalty_start = term.maturity.seconds() + term.grace_period;
+        let penalty_elapsed = ts.seconds().saturating_sub(std::cmp::max(self.last_update_time.seconds(), penalty_start));
+        let interest = self.principal * (term.rate * Decimal::from_ratio(elapsed, SECONDS_PER_YEAR))
+            + self.principal * (term.penalty_rate * Decimal::from_ratio(penalty_elapsed, SECONDS_PER_YEAR));
+        LoanInfo {
+            interest: self.interest + interest,
+            last_update_time: ts,
+            ..self.clone()
+        }
+    }
+
+    /// Current amount owed on the loan, given an up to date market state and,
+    /// for term loans, an up to date loan
+    pub fn amount_owed(&self, state: &MarketState) -> Uint128 {
+        match self.term {
+            Some(_) => self.principal + self.interest,
+            None => state.unscale(self.scaled_debt),
         }
     }
 }
 
+/// Flash loan sent out and waiting to be checked in the `reply` to its
+/// callback
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FlashLoanState {
+    pub token: String,
+    /// contract balance of `token` before the loan was sent
+    pub balance_before: Uint128,
+    /// market deposits before the loan was sent, deposits and withdrawals
+    /// made by the callback move the balance as well
+    pub deposits_before: Uint128,
+    pub fee: Uint128,
+}
+
+/// Borrowing another address may do against the owner's collateral
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BorrowDelegation {
+    /// amount left to borrow, lowered by every delegated borrow
+    pub limit: Uint128,
+    pub expires: Expiration,
+}
+
 pub const CONFIG: Item<Config> = Item::new("Config");
+pub const MARKET_STATE: Item<MarketState> = Item::new("MarketState");
+pub const RATE_MODEL: Item<InterestRateModelConfig> = Item::new("RateModel");
+pub const MARKETS: Map<&str, MarketConfig> = Map::new("Market");
+pub const COLLATERAL_STATE: Map<&str, CollateralState> = Map::new("CollateralState");
 pub const USER_INFO: Map<&Addr, UserData> = Map::new("User");
 pub const LOANS: Map<(&Addr, u128), LoanInfo> = Map::new("Loan");
+/// ids of the open term loans in `LOANS`, which accrue outside the borrow
+/// index and have to be visited to total a user's debt
+pub const TERM_LOANS: Map<(&Addr, u128), Empty> = Map::new("TermLoan");
+pub const ROLES: Map<&Addr, Role> = Map::new("Role");
+pub const PAUSE_STATE: Item<PauseState> = Item::new("PauseState");
+pub const FLASH_LOAN: Item<FlashLoanState> = Item::new("FlashLoan");
+/// delegations by (owner, delegate)
+pub const BORROW_DELEGATIONS: Map<(&Addr, &Addr), BorrowDelegation> = Map::new("BorrowDelegation");
 
 #[cfg(test)]
 mod state_tests {
-    use cosmwasm_std::{Timestamp, Uint128};
+    use cosmwasm_std::{Decimal, Timestamp, Uint128};
 
-    use super::LoanInfo;
+    use super::{CollateralDeposit, CollateralState, LoanInfo, LoanTerm, MarketState, UserData};
+
+    fn borrow(state: &mut MarketState, amount: u128) -> LoanInfo {
+        let principal = Uint128::from(amount);
+        let scaled = state.scale(principal);
+        state.total_scaled_debt += scaled;
+        LoanInfo::new(state.last_accrual_time, principal, scaled)
+    }
 
     #[test]
     fn basic_loan_test() {
-        let ts = Timestamp::from_seconds(0);
-        let mut loan_info = LoanInfo::new(ts, Uint128::from(1000_u128));
-        let ts2 = Timestamp::from_seconds(86400 * 365);
-        let loan_info2 = loan_info.update_loan(ts2);
+        let mut state = MarketState::new(Timestamp::from_seconds(0));
+        let loan_info = borrow(&mut state, 1000);
+        state.accrue(Timestamp::from_seconds(86400 * 365), Decimal::percent(3), Decimal::zero());
         assert_eq!(
-            loan_info2.principal.atomics(),
+            loan_info.amount_owed(&state),
             Uint128::from(1030_u128)
         )
     }
 
     #[test]
     fn multi_year_loan_test() {
-        let ts = Timestamp::from_seconds(0);
-        let mut loan_info = LoanInfo::new(ts, Uint128::from(1000_u128));
-        let ts2 = Timestamp::from_seconds(86400 * 1); // 1 day
-        let mut loan_info2 = loan_info.update_loan(ts2);
-        let ts3 = Timestamp::from_seconds(86400 * 365);
-        let mut loan_info3 = loan_info2.update_loan(ts3);
+        let mut state = MarketState::new(Timestamp::from_seconds(0));
+        let loan_info = borrow(&mut state, 1000);
+        state.accrue(Timestamp::from_seconds(86400), Decimal::percent(3), Decimal::zero()); // 1 day
+        state.accrue(Timestamp::from_seconds(86400 * 365), Decimal::percent(3), Decimal::zero());
         assert_eq!(
-            loan_info3.principal.atomics(),
+            loan_info.amount_owed(&state),
             Uint128::from(1030_u128)
         );
-        let ts4 = Timestamp::from_seconds(86400 * 365 * 3);
-        let loan_info4 = loan_info3.update_loan(ts4);
+        state.accrue(Timestamp::from_seconds(86400 * 365 * 3), Decimal::percent(3), Decimal::zero());
         assert_eq!(
-            loan_info4.principal.atomics(),
+            loan_info.amount_owed(&state),
             Uint128::from(1091_u128)
         )
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn late_loan_test() {
+        // a loan taken out after interest has accrued only owes interest from then on
+        let mut state = MarketState::new(Timestamp::from_seconds(0));
+        borrow(&mut state, 1000);
+        state.accrue(Timestamp::from_seconds(86400 * 365), Decimal::percent(3), Decimal::zero());
+        let loan_info = borrow(&mut state, 1000);
+        assert_eq!(loan_info.amount_owed(&state), Uint128::from(1000_u128));
+        state.accrue(Timestamp::from_seconds(86400 * 365 * 2), Decimal::percent(3), Decimal::zero());
+        assert_eq!(loan_info.amount_owed(&state), Uint128::from(1030_u128));
+        assert_eq!(state.total_borrows(), Uint128::from(2091_u128));
+    }
+
+    #[test]
+    fn utilization_test() {
+        let mut state = MarketState::new(Timestamp::from_seconds(0));
+        let collateral_value = Uint128::from(1000_u128);
+        assert_eq!(state.utilization(collateral_value), Decimal::zero());
+        borrow(&mut state, 250);
+        assert_eq!(state.utilization(collateral_value), Decimal::percent(25));
+        borrow(&mut state, 1000);
+        assert_eq!(state.utilization(collateral_value), Decimal::one());
+    }
+
+    #[test]
+    fn exchange_rate_test() {
+        let mut state = MarketState::new(Timestamp::from_seconds(0));
+        assert_eq!(state.receipts_for(Uint128::from(500_u128)), Uint128::from(500_u128));
+        state.total_supply = Uint128::from(500_u128);
+        state.total_receipts = Uint128::from(500_u128);
+
+        // interest paid into the pool raises the exchange rate
+        state.total_supply += Uint128::from(100_u128);
+        assert_eq!(state.exchange_rate(), Decimal::percent(120));
+        assert_eq!(state.receipts_for(Uint128::from(600_u128)), Uint128::from(500_u128));
+        assert_eq!(state.underlying_for(Uint128::from(250_u128)), Uint128::from(300_u128));
+    }
+
+    #[test]
+    fn supplier_accrual_test() {
+        let mut state = MarketState::new(Timestamp::from_seconds(0));
+        state.total_supply = Uint128::from(500_u128);
+        state.total_receipts = Uint128::from(500_u128);
+        state.total_scaled_debt = Uint128::from(1000_u128);
+        state.total_lent = Uint128::from(500_u128);
+
+        // half the debt is funded by the pool, so suppliers earn half the interest after reserves
+        state.accrue(Timestamp::from_seconds(86400 * 365), Decimal::percent(4), Decimal::percent(50));
+        assert_eq!(state.total_reserves, Uint128::from(20_u128));
+        assert_eq!(state.total_supply, Uint128::from(510_u128));
+        assert_eq!(state.interest_receivable, Uint128::from(30_u128));
+
+        // with no cash, payouts are minted against what the pool is owed
+        assert_eq!(state.pay_out(Uint128::from(520_u128)), Uint128::from(520_u128));
+        assert_eq!(state.total_lent, Uint128::zero());
+        assert_eq!(state.interest_receivable, Uint128::from(10_u128));
+    }
+
+    #[test]
+    fn deposit_receipts_test() {
+        let mut collateral = CollateralState::default();
+        assert_eq!(collateral.receipts_for(Uint128::from(300_u128)), Uint128::from(300_u128));
+        collateral.total_deposits = Uint128::from(300_u128);
+        collateral.total_receipts = Uint128::from(300_u128);
+
+        // fees credited to the deposits raise what each receipt is worth
+        collateral.total_deposits += Uint128::from(100_u128);
+        assert_eq!(collateral.receipts_for(Uint128::from(100_u128)), Uint128::from(75_u128));
+        assert_eq!(collateral.underlying_for(Uint128::from(1_u128)), Uint128::from(1_u128));
+        assert_eq!(collateral.receipts_to_withdraw(Uint128::from(1_u128)), Uint128::from(1_u128));
+        assert_eq!(collateral.receipts_to_withdraw(Uint128::from(4_u128)), Uint128::from(3_u128));
+        assert_eq!(collateral.receipts_to_withdraw(Uint128::from(5_u128)), Uint128::from(4_u128));
+    }
+
+    #[test]
+    fn multi_collateral_deposit_test() {
+        let user_data = UserData::new()
+            .deposit_amount("token_a", Uint128::from(100_u128))
+            .deposit_amount("uatom", Uint128::from(50_u128))
+            .deposit_amount("token_a", Uint128::from(20_u128));
+        assert_eq!(user_data.deposited("token_a"), Uint128::from(120_u128));
+        assert_eq!(user_data.deposited("uatom"), Uint128::from(50_u128));
+
+        // fully withdrawn tokens are dropped
+        let user_data = user_data.withdraw_amount("uatom", Uint128::from(50_u128));
+        assert_eq!(user_data.deposits, vec![CollateralDeposit { token: "token_a".to_string(), amount: Uint128::from(120_u128) }]);
+    }
+
+    #[test]
+    fn supply_only_deposit_test() {
+        let user_data = UserData::new()
+            .deposit_amount("token_a", Uint128::from(100_u128))
+            .deposit_amount("uatom", Uint128::from(50_u128))
+            .set_collateral("uatom", false);
+        assert!(!user_data.is_collateral("uatom"));
+        assert_eq!(user_data.collateral(), vec![CollateralDeposit { token: "token_a".to_string(), amount: Uint128::from(100_u128) }]);
+
+        // the setting is kept across deposits and withdrawals
+        let user_data = user_data.withdraw_amount("uatom", Uint128::from(50_u128)).deposit_amount("uatom", Uint128::from(10_u128));
+        assert_eq!(user_data.collateral().len(), 1);
+        let user_data = user_data.set_collateral("uatom", true);
+        assert_eq!(user_data.collateral(), user_data.deposits);
+        assert!(user_data.supply_only.is_empty());
+    }
+
+    #[test]
+    fn term_loan_test() {
+        let term = LoanTerm {
+            maturity: Timestamp::from_seconds(86400 * 365),
+            rate: Decimal::percent(5),
+            penalty_rate: Decimal::percent(20),
+            grace_period: 86400 * 30,
+        };
+        let state = MarketState::new(Timestamp::from_seconds(0));
+        let loan_info = LoanInfo::new_term(Timestamp::from_seconds(0), Uint128::from(1000_u128), term);
+
+        // the fixed rate applies until maturity and through the grace period
+        let loan_info = loan_info.update_loan(Timestamp::from_seconds(86400 * 365));
+        assert_eq!(loan_info.amount_owed(&state), Uint128::from(1050_u128));
+        let loan_info = loan_info.update_loan(Timestamp::from_seconds(86400 * 395));
+        assert_eq!(loan_info.amount_owed(&state), Uint128::from(1054_u128));
+
+        // past the grace period the penalty rate is added on top
+        let updated = loan_info.update_loan(Timestamp::from_seconds(86400 * 760));
+        assert_eq!(updated.amount_owed(&state), Uint128::from(1304_u128));
+        // updating in steps accrues the same interest
+        let stepped = loan_info
+            .update_loan(Timestamp::from_seconds(86400 * 468))
+            .update_loan(Timestamp::from_seconds(86400 * 760));
+        assert_eq!(stepped.amount_owed(&state), Uint128::from(1304_u128));
+    }
+}